@@ -1,13 +1,87 @@
 #![no_std]
-use soroban_sdk::{contract, contractimpl, contracttype, Address, Env, Vec, Map, Symbol, log, panic_with_error, contracterror};
+use soroban_sdk::{contract, contractclient, contractimpl, contracttype, token, Address, Bytes, BytesN, Env, IntoVal, String, Val, Vec, Map, Symbol, panic_with_error, contracterror};
+
+// Who absorbs the stroops left over when an amount does not divide evenly
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[contracttype]
+pub enum RemainderPolicy {
+    Payer,             // the payer's share absorbs the whole remainder
+    RoundRobin,        // one stroop each, in member order, starting at tx_id % participants
+    LargestRemainder,  // one stroop each to the largest fractional parts, ties in member order
+}
+
+// How an expense is divided between group members
+#[derive(Clone, Debug)]
+#[contracttype]
+pub enum Split {
+    Equal(Vec<Symbol>),       // equal shares, an empty list means every member
+    Shares(Map<Symbol, u32>), // proportional to the given weights
+    Exact(Map<Symbol, u64>),  // fixed amounts that must add up to the expense
+}
+
+// Approvals an expense needs before it changes any balance
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[contracttype]
+pub enum Quorum {
+    Count(u32),    // fixed number of members, capped at the group size
+    Percent(u32),  // share of the members, rounded up
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[contracttype]
+pub enum TxStatus {
+    Pending,
+    Approved,
+    Rejected,
+    Expired,
+    Cancelled,  // withdrawn by its author while pending
+    Reversed,   // approved, then undone by an approved reversal entry
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[contracttype]
+pub enum Role {
+    Owner,
+    Admin,
+    Member,
+    Viewer,
+}
+
+// Group operations gated by role, see `MappingContract::allows`
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[contracttype]
+pub enum Action {
+    ManageMembers,
+    PostExpense,
+    Approve,
+    ConfigureSplit,
+    ManageRecurring,
+    CloseGroup,
+}
 
 #[derive(Clone, Debug)]
 #[contracttype]
 pub struct Group {
     group_id: u64,
     owner: Address,
-    total_amount: u64,
+    assets: Vec<Address>,      // token contracts expenses can be recorded in
+    totals: Map<Address, u64>, // approved expense volume per asset
+    members: Vec<Symbol>,
+    remainder_policy: RemainderPolicy,
+    quorum: Quorum,
+    approval_window: u32,  // ledgers a pending expense stays open, 0 for no expiry
+}
+
+// What remains of a group once it is closed
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[contracttype]
+pub struct GroupSummary {
+    group_id: u64,
+    owner: Address,
     members: Vec<Symbol>,
+    totals: Map<Address, u64>,
+    transaction_count: u32,
+    closed_at: u64,  // ledger timestamp
 }
 
 #[derive(Clone)]
@@ -15,17 +89,203 @@ pub struct Group {
 pub struct Member {
     user_id: Symbol,
     address: Address,
-    group_balances: Map<u64, u64>,
+}
+
+// A group member without an address yet, keyed by its user id
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[contracttype]
+pub struct Guest {
+    group_id: u64,
+    name: String,  // display name, at most MAX_NAME_LEN bytes
 }
 
 #[derive(Clone)]
 #[contracttype]
 pub struct Transaction {
+    tx_id: u64,
     user_id: Symbol,
     group_id: u64,
     amount: u64,
-    proof: Symbol,  // IPFS link
+    asset: Address,
+    attachments: Vec<Attachment>,
+    approvals: Vec<Symbol>,
+    payers: Map<Symbol, u64>,  // what each payer contributed, summing to `amount`
+    shares: Map<Symbol, u64>,
+    rejections: Vec<Symbol>,
+    status: TxStatus,
+    created_ledger: u32,
+    reverses: u64,  // id of the expense this entry undoes, 0 for a regular expense
+}
+
+// A receipt or other proof, addressed by the sha256 of its content so a
+// downloaded copy can be checked against what was posted
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[contracttype]
+pub struct Attachment {
+    hash: BytesN<32>,
+    uri: String,   // e.g. ipfs://<cid>, at most MAX_URI_LEN bytes
+    mime: String,  // at most MAX_MIME_LEN bytes
+}
+
+// One line of an itemized bill, split equally between `members`. A line
+// that costs nothing, e.g. a comped item, stays on the bill unsplit.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[contracttype]
+pub struct Item {
+    label: String,
+    price: u64,
+    quantity: u32,
+    members: Vec<Symbol>,  // empty means the whole group
+}
+
+// Tax, tip and discount are allocated in proportion to item subtotals
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[contracttype]
+pub struct Bill {
+    items: Vec<Item>,
+    tax: u64,
+    tip: u64,
+    discount: u64,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[contracttype]
+pub enum Interval {
+    Ledgers(u32),
+    Seconds(u64),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[contracttype]
+pub enum RecurringStatus {
+    Active,
+    Paused,
+    Cancelled,
+    Finished,
+}
+
+// Template for an expense that repeats on a schedule. `next_due` and `end`
+// are ledger sequences or timestamps, matching the interval.
+#[derive(Clone, Debug)]
+#[contracttype]
+pub struct Recurring {
+    recurring_id: u64,
+    group_id: u64,
+    payer: Symbol,
+    amount: u64,
+    asset: Address,
+    attachments: Vec<Attachment>,
+    split: Split,
+    interval: Interval,
+    next_due: u64,
+    end: u64,  // last moment an occurrence may fall on, 0 for no end
+    status: RecurringStatus,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[contracttype]
+pub enum LoanStatus {
+    Open,
+    Repaid,
+    Overdue,
+    Forgiven,
+}
+
+// IOU between two members, settled directly through the asset's token
+#[derive(Clone, Debug)]
+#[contracttype]
+pub struct Loan {
+    loan_id: u64,
+    group_id: u64,
+    lender: Symbol,
+    borrower: Symbol,
+    asset: Address,
+    principal: i128,
+    interest_bps: u32,  // simple yearly interest, accrued up to the due date
+    start: u64,
+    due: u64,           // ledger timestamp
+    total_due: i128,
+    repaid: i128,
+    status: LoanStatus,
+}
+
+// Payment out of the group treasury, executed once the quorum approves it
+#[derive(Clone, Debug)]
+#[contracttype]
+pub struct Withdrawal {
+    withdrawal_id: u64,
+    group_id: u64,
+    proposer: Symbol,
+    asset: Address,
+    to: Address,
+    amount: i128,
     approvals: Vec<Symbol>,
+    rejections: Vec<Symbol>,
+    status: TxStatus,
+    created_ledger: u32,
+}
+
+// Asset identifier of the SEP-40 price feed interface
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[contracttype]
+pub enum OracleAsset {
+    Stellar(Address),
+    Other(Symbol),
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[contracttype]
+pub struct PriceData {
+    price: i128,
+    timestamp: u64,
+}
+
+// The subset of SEP-40 used to convert expenses into the group base currency
+#[contractclient(name = "PriceOracleClient")]
+pub trait PriceOracle {
+    fn decimals(env: Env) -> u32;
+    fn lastprice(env: Env, asset: OracleAsset) -> Option<PriceData>;
+}
+
+// Lookup side of the username registry contract
+#[contractclient(name = "RegistryClient")]
+pub trait Registry {
+    fn owner_of(env: Env, handle: Symbol) -> Option<Address>;
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[contracttype]
+pub struct OracleConfig {
+    oracle: Address,
+    base_asset: Address,  // one of the group assets, all positions of converted expenses land here
+    max_age: u64,         // seconds a price may lag behind the ledger timestamp
+}
+
+// Rate an expense was converted at, kept for auditing
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[contracttype]
+pub struct Conversion {
+    asset: Address,
+    amount: u64,
+    rate: i128,      // base stroops per asset stroop, scaled by 10^decimals
+    decimals: u32,   // oracle decimals, plus any extra the token decimals call for
+    timestamp: u64,  // the older of the two oracle prices used
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[contracttype]
+pub struct Settlement {
+    from: Symbol,
+    to: Symbol,
+    amount: i128,
+}
+
+// How a departing member's non-zero position is cleared
+#[derive(Clone, Debug)]
+#[contracttype]
+pub enum Resolution {
+    Settle,            // token transfers with the members on the other side, per asset, not for guests
+    Reassign(Symbol),  // another member takes over the positions
 }
 
 #[derive(Clone, PartialEq, Eq)]
@@ -34,9 +294,43 @@ pub enum DataKey {
     Group(u64),
     Member(Symbol),
     Transaction(u64),
+    Position(u64, Symbol, Address),  // net position per asset: paid minus owed
+    Role(u64, Address),  // only admins and viewers, everyone else holds the default role
+    PendingOwner(u64),
+    Invite(u64, Address),
+    InviteCode(u64),  // sha256 of the secret shared in the group link
+    Oracle(u64),
+    Conversion(u64),  // per transaction id, only for converted expenses
+    Bill(u64),        // per transaction id, only for itemized expenses
+    LastBump(u64),    // ledger of the group's last full TTL extension
+    Reversal(u64),    // expense id -> id of the latest entry reversing it
+    Summary(u64),     // replaces every other entry of the group once closed
+    Guest(Symbol),    // shares the user id namespace with Member
+    GuestClaim(Symbol),
+    Admin,     // instance storage, like Registry, so both live as long as the contract
+    Registry,  // once set, user ids are handles owned in the registry
+    Treasury(u64, Address),
+    Deposit(u64, Symbol, Address),  // running total, the basis of the refund on close
+    Depositors(u64, Address),
+    Withdrawal(u64),
+    LastWithdrawalId,
+    Recurring(u64),
+    LastRecurringId,
+    Loan(u64),
+    LastLoanId,
+    // Ids of the group's entries that are still live, so `bump_group` can
+    // reach them: pending withdrawals, active or paused recurring expenses
+    // and open loans
+    GroupWithdrawals(u64),
+    GroupRecurring(u64),
+    GroupLoans(u64),
     LastGroupId,
     LastTransactionId,
-    GroupList,  // List of all groups
+    GroupTxCount(u64),
+    GroupTxPage(u64, u32),  // INDEX_PAGE_SIZE transaction ids per entry
+    MembershipCount(Address),
+    Membership(Address, u32),     // position -> group id
+    MembershipPos(Address, u64),  // group id -> position
 }
 
 #[contracterror]
@@ -50,161 +344,2916 @@ pub enum Error {
     MemberNotFound = 5,
     UserIdAlreadyExists = 6,
     GroupCreationFailed = 7,
+    InvalidSplit = 8,
+    InvalidAmount = 9,
+    TransactionNotFound = 10,
+    TransactionNotPending = 11,
+    AlreadyVoted = 12,
+    InvalidQuorum = 13,
+    NotExpired = 14,
+    OutstandingBalance = 15,
+    InvalidRole = 16,
+    InviteNotFound = 17,
+    InvalidInviteCode = 18,
+    AssetNotAccepted = 19,
+    PriceUnavailable = 20,
+    StalePrice = 21,
+    InsufficientTreasury = 22,
+    WithdrawalNotFound = 23,
+    RecurringNotFound = 24,
+    NotDue = 25,
+    InvalidSchedule = 26,
+    LoanNotFound = 27,
+    LoanClosed = 28,
+    InvalidAttachment = 29,
+    InvalidReversal = 30,
+    GroupClosed = 31,
+    ClaimNotFound = 32,
+    InvalidName = 33,
+    HandleNotOwned = 34,
+    MultiplePayers = 35,
+    GuestCannotSettle = 36,
+    AlreadyInitialized = 37,
 }
 
+// Group id in the topics of events that concern no group, ids start at 1
+const NO_GROUP: u64 = 0;
+
+// Upper bound on entries returned by the list views
+const MAX_PAGE_SIZE: u32 = 20;
+const INDEX_PAGE_SIZE: u32 = 50;
+const SECONDS_PER_YEAR: u64 = 365 * 24 * 60 * 60;
+const MAX_ATTACHMENTS: u32 = 5;
+const MAX_URI_LEN: u32 = 256;
+const MAX_MIME_LEN: u32 = 64;
+const MAX_NAME_LEN: u32 = 64;
+
+// Entries are extended to BUMP_AMOUNT whenever they are written, or read
+// with less than BUMP_THRESHOLD left
+const DAY_IN_LEDGERS: u32 = 17_280;
+const BUMP_AMOUNT: u32 = 30 * DAY_IN_LEDGERS;
+const BUMP_THRESHOLD: u32 = BUMP_AMOUNT - DAY_IN_LEDGERS;
+
 #[contract]
 pub struct MappingContract;
 
 #[contractimpl]
 impl MappingContract {
     pub fn create_group(env: &Env, owner: Address) -> u64 {
-        log!(env, "Entering create_group function");
-        log!(env, "Owner address: {:?}", owner);
-
         let group_id = Self::get_next_group_id(env);
-        log!(env, "Generated new group_id: {}", group_id);
-
-        let owner_symbol = Symbol::short("owner");
-        log!(env, "Created owner symbol: {:?}", owner_symbol);
-
-        let members = Vec::from_array(env, [owner_symbol]);
-        log!(env, "Created members vector: {:?}", members);
 
         let group = Group {
             group_id,
             owner: owner.clone(),
-            total_amount: 0,
+            assets: Vec::new(env),
+            totals: Map::new(env),
             members: Vec::new(env),
+            remainder_policy: RemainderPolicy::Payer,
+            quorum: Quorum::Count(1),
+            approval_window: 0,
         };
-        log!(env, "Created new group: {:?}", group);
 
         // Store the individual group
-        env.storage().persistent().set(&DataKey::Group(group_id), &group);
-        log!(env, "Stored individual group in persistent storage");
+        Self::save(env, &DataKey::Group(group_id), &group);
+        Self::save(env, &DataKey::LastBump(group_id), &env.ledger().sequence());
+
+        Self::publish(env, "group_created", group_id, &owner, ());
+        group_id
+    }
 
-        // Update the list of all groups
-        let mut group_list = env.storage().persistent().get::<DataKey, Vec<Group>>(&DataKey::GroupList)
-            .unwrap_or_else(|| {
-                log!(env, "Group list not found, creating new list");
-                Vec::new(env)
-            });
-        log!(env, "Current group list size: {}", group_list.len());
+    // Records the contract admin once. The deployer calls it right after
+    // deploying, if someone else got there first it fails and the contract
+    // is deployed again.
+    pub fn init(env: &Env, admin: Address) {
+        admin.require_auth();
+        if env.storage().instance().has(&DataKey::Admin) {
+            panic_with_error!(env, Error::AlreadyInitialized);
+        }
 
-        group_list.push_back(group);
-        log!(env, "Added new group to list. New size: {}", group_list.len());
+        env.storage().instance().set(&DataKey::Admin, &admin);
+        env.storage().instance().extend_ttl(BUMP_THRESHOLD, BUMP_AMOUNT);
+        Self::publish(env, "initialized", NO_GROUP, &admin, ());
+    }
 
-        env.storage().persistent().set(&DataKey::GroupList, &group_list);
-        log!(env, "Stored updated group list in persistent storage");
+    pub fn set_registry(env: &Env, registry: Address) {
+        let admin = env.storage().instance().get::<DataKey, Address>(&DataKey::Admin)
+            .unwrap_or_else(|| panic_with_error!(env, Error::Unauthorized));
+        admin.require_auth();
 
-        log!(env, "Exiting create_group function. Returning group_id: {}", group_id);
-        group_id
+        env.storage().instance().set(&DataKey::Registry, &registry);
+        env.storage().instance().extend_ttl(BUMP_THRESHOLD, BUMP_AMOUNT);
+        Self::publish(env, "registry_set", NO_GROUP, &admin, registry);
     }
 
+    // With a registry set, `user_id` has to be a handle `address` owns there
     pub fn create_member(env: &Env, user_id: Symbol, address: Address) {
-        if env.storage().persistent().has(&DataKey::Member(user_id.clone())) {
+        if env.storage().persistent().has(&DataKey::Member(user_id.clone())) || env.storage().persistent().has(&DataKey::Guest(user_id.clone())) {
             panic_with_error!(env, Error::UserIdAlreadyExists);
         }
+        Self::check_handle(env, &user_id, Some(&address));
         let member = Member {
             user_id: user_id.clone(),
-            address,
-            group_balances: Map::new(env),
+            address: address.clone(),
         };
-        env.storage().persistent().set(&DataKey::Member(user_id), &member);
+        Self::save(env, &DataKey::Member(user_id.clone()), &member);
+        Self::publish(env, "member_created", NO_GROUP, &address, user_id);
     }
 
+    // Direct enrollment, needs both the caller's permission and the new
+    // member's own signature
     pub fn add_member_to_group(env: &Env, group_id: u64, user_id: Symbol, caller: Address) {
-        let mut group = env.storage().persistent().get::<DataKey, Group>(&DataKey::Group(group_id))
-            .unwrap_or_else(|| panic_with_error!(env, Error::GroupNotFound));
+        let group = Self::load_group(env, group_id);
+
+        Self::require_permission(env, &group, &caller, Action::ManageMembers);
+        Self::get_member_address(env, &user_id).require_auth();
+
+        Self::enroll(env, group, user_id);
+    }
+
+    // Adds someone who has no wallet yet. A guest shares in expenses and
+    // holds positions like any member, but cannot sign until an address
+    // claims it.
+    pub fn add_guest(env: &Env, group_id: u64, guest_id: Symbol, name: String, caller: Address) {
+        let mut group = Self::load_group(env, group_id);
+
+        Self::require_permission(env, &group, &caller, Action::ManageMembers);
+
+        if env.storage().persistent().has(&DataKey::Member(guest_id.clone())) || env.storage().persistent().has(&DataKey::Guest(guest_id.clone())) {
+            panic_with_error!(env, Error::UserIdAlreadyExists);
+        }
+        if name.is_empty() || name.len() > MAX_NAME_LEN {
+            panic_with_error!(env, Error::InvalidName);
+        }
+        // A guest may not hold on to someone else's handle
+        Self::check_handle(env, &guest_id, None);
+
+        group.members.push_back(guest_id.clone());
+        Self::save(env, &DataKey::Group(group_id), &group);
+        Self::save(env, &DataKey::Guest(guest_id.clone()), &Guest { group_id, name });
+        Self::publish(env, "guest_added", group_id, &caller, guest_id);
+    }
+
+    pub fn get_guest(env: &Env, guest_id: Symbol) -> Guest {
+        env.storage().persistent().get::<DataKey, Guest>(&DataKey::Guest(guest_id))
+            .unwrap_or_else(|| panic_with_error!(env, Error::MemberNotFound))
+    }
+
+    // Asks to take over a guest. A later claim replaces a pending one, the
+    // owner approves a specific address.
+    pub fn claim_guest(env: &Env, guest_id: Symbol, address: Address) {
+        address.require_auth();
+        let guest = Self::get_guest(env, guest_id.clone());
+
+        Self::save(env, &DataKey::GuestClaim(guest_id.clone()), &address);
+        Self::publish(env, "guest_claim", guest.group_id, &address, guest_id);
+    }
+
+    // The guest id becomes a regular member id of `address`, so positions
+    // and history carry over unchanged
+    pub fn approve_claim(env: &Env, guest_id: Symbol, address: Address, caller: Address) {
+        caller.require_auth();
+        let guest = Self::get_guest(env, guest_id.clone());
+        let group = Self::load_group(env, guest.group_id);
 
         if group.owner != caller {
             panic_with_error!(env, Error::Unauthorized);
         }
+        if env.storage().persistent().get::<DataKey, Address>(&DataKey::GuestClaim(guest_id.clone())) != Some(address.clone()) {
+            panic_with_error!(env, Error::ClaimNotFound);
+        }
+        Self::check_handle(env, &guest_id, Some(&address));
 
-        if group.members.contains(&user_id) {
-            panic_with_error!(env, Error::MemberAlreadyExists);
+        env.storage().persistent().remove(&DataKey::GuestClaim(guest_id.clone()));
+        env.storage().persistent().remove(&DataKey::Guest(guest_id.clone()));
+        Self::save(env, &DataKey::Member(guest_id.clone()), &Member { user_id: guest_id.clone(), address: address.clone() });
+        Self::index_membership(env, &address, group.group_id);
+        Self::publish(env, "guest_claimed", group.group_id, &caller, (guest_id, address));
+    }
+
+    pub fn invite(env: &Env, group_id: u64, address: Address, caller: Address) {
+        let group = Self::load_group(env, group_id);
+
+        Self::require_permission(env, &group, &caller, Action::ManageMembers);
+
+        Self::save(env, &DataKey::Invite(group_id, address.clone()), &true);
+        Self::publish(env, "member_invited", group_id, &caller, address);
+    }
+
+    pub fn has_invite(env: &Env, group_id: u64, address: Address) -> bool {
+        env.storage().persistent().has(&DataKey::Invite(group_id, address))
+    }
+
+    pub fn accept_invite(env: &Env, group_id: u64, user_id: Symbol) {
+        let group = Self::load_group(env, group_id);
+        let address = Self::get_member_address(env, &user_id);
+        address.require_auth();
+
+        let key = DataKey::Invite(group_id, address);
+        if !env.storage().persistent().has(&key) {
+            panic_with_error!(env, Error::InviteNotFound);
         }
+        env.storage().persistent().remove(&key);
 
-        group.members.push_back(user_id.clone());
-        env.storage().persistent().set(&DataKey::Group(group_id), &group);
+        Self::enroll(env, group, user_id);
+    }
 
-        let mut member = env.storage().persistent().get::<DataKey, Member>(&DataKey::Member(user_id.clone()))
-            .unwrap_or_else(|| panic_with_error!(env, Error::MemberNotFound));
-        member.group_balances.set(group_id, 0);
-        env.storage().persistent().set(&DataKey::Member(user_id), &member);
+    pub fn decline_invite(env: &Env, group_id: u64, address: Address) {
+        address.require_auth();
+
+        let key = DataKey::Invite(group_id, address.clone());
+        if !env.storage().persistent().has(&key) {
+            panic_with_error!(env, Error::InviteNotFound);
+        }
+        env.storage().persistent().remove(&key);
+        Self::publish(env, "invite_declined", group_id, &address, ());
     }
 
-    pub fn add_transaction(env: &Env, user_id: Symbol, group_id: u64, amount: u64, proof: Symbol) -> u64 {
-        let tx_id = Self::get_next_transaction_id(env);
-        let transaction = Transaction {
-            user_id: user_id.clone(),
-            group_id,
-            amount,
-            proof,
-            approvals: Vec::new(env),
-        };
-        env.storage().persistent().set(&DataKey::Transaction(tx_id), &transaction);
+    // Only the hash goes on-chain, whoever knows the secret can join until
+    // the code is replaced or revoked
+    pub fn set_invite_code(env: &Env, group_id: u64, code_hash: BytesN<32>, caller: Address) {
+        let group = Self::load_group(env, group_id);
 
-        // Update group total amount
-        let mut group = env.storage().persistent().get::<DataKey, Group>(&DataKey::Group(group_id))
-            .unwrap_or_else(|| panic_with_error!(env, Error::GroupNotFound));
-        group.total_amount += amount;
-        env.storage().persistent().set(&DataKey::Group(group_id), &group);
+        Self::require_permission(env, &group, &caller, Action::ManageMembers);
 
-        // Update member's balance in the group
-        let mut member = env.storage().persistent().get::<DataKey, Member>(&DataKey::Member(user_id.clone()))
-            .unwrap_or_else(|| panic_with_error!(env, Error::MemberNotFound));
-        let new_balance = member.group_balances.get(group_id).unwrap_or(0) + amount;
-        member.group_balances.set(group_id, new_balance);
-        env.storage().persistent().set(&DataKey::Member(user_id.clone()), &member);
+        Self::save(env, &DataKey::InviteCode(group_id), &code_hash);
+        Self::publish(env, "invite_code_set", group_id, &caller, code_hash);
+    }
 
-        tx_id
+    pub fn revoke_invite_code(env: &Env, group_id: u64, caller: Address) {
+        let group = Self::load_group(env, group_id);
+
+        Self::require_permission(env, &group, &caller, Action::ManageMembers);
+
+        env.storage().persistent().remove(&DataKey::InviteCode(group_id));
+        Self::publish(env, "invite_code_revoked", group_id, &caller, ());
     }
 
-    pub fn approve_transaction(env: &Env, tx_id: u64, approver_id: Symbol) {
-        let mut transaction = env.storage().persistent().get::<DataKey, Transaction>(&DataKey::Transaction(tx_id))
-            .unwrap_or_else(|| panic_with_error!(env, Error::GroupNotFound));
-        
-        let group = env.storage().persistent().get::<DataKey, Group>(&DataKey::Group(transaction.group_id))
-            .unwrap_or_else(|| panic_with_error!(env, Error::GroupNotFound));
+    pub fn join_with_code(env: &Env, group_id: u64, user_id: Symbol, secret: Bytes) {
+        let group = Self::load_group(env, group_id);
+        Self::get_member_address(env, &user_id).require_auth();
+
+        let code_hash = env.storage().persistent().get::<DataKey, BytesN<32>>(&DataKey::InviteCode(group_id))
+            .unwrap_or_else(|| panic_with_error!(env, Error::InvalidInviteCode));
+        if env.crypto().sha256(&secret).to_bytes() != code_hash {
+            panic_with_error!(env, Error::InvalidInviteCode);
+        }
+
+        Self::enroll(env, group, user_id);
+    }
+
+    pub fn remove_member(env: &Env, group_id: u64, user_id: Symbol, resolution: Option<Resolution>, caller: Address) {
+        let group = Self::load_group(env, group_id);
+
+        Self::require_permission(env, &group, &caller, Action::ManageMembers);
+
+        Self::detach_member(env, group, user_id, resolution);
+    }
+
+    pub fn leave_group(env: &Env, group_id: u64, user_id: Symbol, resolution: Option<Resolution>) {
+        let group = Self::load_group(env, group_id);
+        Self::get_member_address(env, &user_id).require_auth();
+
+        Self::detach_member(env, group, user_id, resolution);
+    }
 
-        if !group.members.contains(&approver_id) {
+    // Owner-only, the owner role itself moves with `transfer_ownership`
+    pub fn set_role(env: &Env, group_id: u64, user_id: Symbol, role: Role, caller: Address) {
+        caller.require_auth();
+        let group = Self::load_group(env, group_id);
+
+        if group.owner != caller {
             panic_with_error!(env, Error::Unauthorized);
         }
+        if !group.members.contains(&user_id) {
+            panic_with_error!(env, Error::MemberNotFound);
+        }
 
-        if !transaction.approvals.contains(&approver_id) {
-            transaction.approvals.push_back(approver_id);
-            env.storage().persistent().set(&DataKey::Transaction(tx_id), &transaction);
+        let address = Self::get_member_address(env, &user_id);
+        if address == group.owner {
+            panic_with_error!(env, Error::InvalidRole);
+        }
+        let key = DataKey::Role(group_id, address);
+        match role {
+            Role::Owner => panic_with_error!(env, Error::InvalidRole),
+            Role::Member => env.storage().persistent().remove(&key),
+            Role::Admin | Role::Viewer => Self::save(env, &key, &role),
         }
+        Self::publish(env, "role_changed", group_id, &caller, (user_id, role));
     }
 
-    pub fn get_all_groups(env: &Env) -> Vec<Group> {
-        env.storage().persistent().get::<DataKey, Vec<Group>>(&DataKey::GroupList)
-            .unwrap_or_else(|| Vec::new(env))
+    pub fn get_role(env: &Env, group_id: u64, user_id: Symbol) -> Role {
+        let group = Self::load_group(env, group_id);
+        if !group.members.contains(&user_id) {
+            panic_with_error!(env, Error::MemberNotFound);
+        }
+        Self::role_of(env, &group, &Self::get_member_address(env, &user_id)).unwrap_or(Role::Member)
     }
 
-    fn get_next_group_id(env: &Env) -> u64 {
-        log!(env, "Entering get_next_group_id function");
+    pub fn has_permission(env: &Env, group_id: u64, address: Address, action: Action) -> bool {
+        let group = Self::load_group(env, group_id);
+        Self::role_of(env, &group, &address).is_some_and(|role| Self::allows(role, action))
+    }
 
-        let last_id = env.storage().persistent().get::<DataKey, u64>(&DataKey::LastGroupId).unwrap_or(0);
-        log!(env, "Last group ID: {}", last_id);
+    // Ownership only moves once the new owner accepts it
+    pub fn transfer_ownership(env: &Env, group_id: u64, new_owner: Address, caller: Address) {
+        caller.require_auth();
+        let group = Self::load_group(env, group_id);
 
-        let new_id = last_id.checked_add(1).unwrap_or_else(|| {
-            log!(env, "Group ID overflow occurred");
-            panic_with_error!(env, Error::GroupCreationFailed);
-        });
-        log!(env, "New group ID: {}", new_id);
+        if group.owner != caller {
+            panic_with_error!(env, Error::Unauthorized);
+        }
 
-        env.storage().persistent().set(&DataKey::LastGroupId, &new_id);
-        log!(env, "Stored new last group ID in persistent storage");
+        Self::save(env, &DataKey::PendingOwner(group_id), &new_owner);
+        Self::publish(env, "ownership_offered", group_id, &caller, new_owner);
+    }
 
-        log!(env, "Exiting get_next_group_id function. Returning new_id: {}", new_id);
-        new_id
+    pub fn accept_ownership(env: &Env, group_id: u64, new_owner: Address) {
+        new_owner.require_auth();
+        let mut group = Self::load_group(env, group_id);
+
+        if env.storage().persistent().get::<DataKey, Address>(&DataKey::PendingOwner(group_id)) != Some(new_owner.clone()) {
+            panic_with_error!(env, Error::Unauthorized);
+        }
+
+        env.storage().persistent().remove(&DataKey::PendingOwner(group_id));
+        env.storage().persistent().remove(&DataKey::Role(group_id, new_owner.clone()));
+        let previous = group.owner.clone();
+        group.owner = new_owner.clone();
+        Self::save(env, &DataKey::Group(group_id), &group);
+        Self::publish(env, "owner_changed", group_id, &new_owner, previous);
     }
 
-    fn get_next_transaction_id(env: &Env) -> u64 {
-        let last_id = env.storage().persistent().get::<DataKey, u64>(&DataKey::LastTransactionId).unwrap_or(0);
-        let new_id = last_id.checked_add(1).unwrap_or_else(|| panic_with_error!(env, Error::GroupCreationFailed));
-        env.storage().persistent().set(&DataKey::LastTransactionId, &new_id);
-        new_id
+    pub fn add_asset(env: &Env, group_id: u64, token: Address, caller: Address) {
+        let mut group = Self::load_group(env, group_id);
+
+        Self::require_permission(env, &group, &caller, Action::ConfigureSplit);
+
+        if !group.assets.contains(&token) {
+            group.assets.push_back(token.clone());
+            Self::save(env, &DataKey::Group(group_id), &group);
+            Self::publish(env, "asset_added", group_id, &caller, token);
+        }
+    }
+
+    // Makes the group convert expenses recorded in any other accepted asset
+    // into `base_asset` with prices from a SEP-40 oracle
+    pub fn set_oracle(env: &Env, group_id: u64, oracle: Address, base_asset: Address, max_age: u64, caller: Address) {
+        let group = Self::load_group(env, group_id);
+
+        Self::require_permission(env, &group, &caller, Action::ConfigureSplit);
+
+        if !group.assets.contains(&base_asset) {
+            panic_with_error!(env, Error::AssetNotAccepted);
+        }
+
+        Self::save(env, &DataKey::Oracle(group_id), &OracleConfig { oracle: oracle.clone(), base_asset: base_asset.clone(), max_age });
+        Self::publish(env, "oracle_set", group_id, &caller, (oracle, base_asset));
+    }
+
+    pub fn get_conversion(env: &Env, tx_id: u64) -> Option<Conversion> {
+        env.storage().persistent().get::<DataKey, Conversion>(&DataKey::Conversion(tx_id))
+    }
+
+    pub fn set_remainder_policy(env: &Env, group_id: u64, policy: RemainderPolicy, caller: Address) {
+        let mut group = Self::load_group(env, group_id);
+
+        Self::require_permission(env, &group, &caller, Action::ConfigureSplit);
+
+        group.remainder_policy = policy;
+        Self::save(env, &DataKey::Group(group_id), &group);
+        Self::publish(env, "remainder_policy_set", group_id, &caller, policy);
+    }
+
+    // Sets how many approvals an expense needs and for how many ledgers it
+    // can collect them. The author's own vote counts, so the default quorum
+    // of one applies expenses as soon as they are posted.
+    pub fn set_quorum(env: &Env, group_id: u64, quorum: Quorum, approval_window: u32, caller: Address) {
+        let mut group = Self::load_group(env, group_id);
+
+        Self::require_permission(env, &group, &caller, Action::ConfigureSplit);
+
+        match quorum {
+            Quorum::Count(0) | Quorum::Percent(0) => panic_with_error!(env, Error::InvalidQuorum),
+            Quorum::Percent(percent) if percent > 100 => panic_with_error!(env, Error::InvalidQuorum),
+            _ => {}
+        }
+
+        group.quorum = quorum;
+        group.approval_window = approval_window;
+        Self::save(env, &DataKey::Group(group_id), &group);
+        Self::publish(env, "quorum_set", group_id, &caller, (quorum, approval_window));
+    }
+
+    // With an oracle configured, expenses in other assets are converted to the
+    // base asset first and `split` applies to the converted amount. Exact
+    // splits are given in the original asset and scaled proportionally.
+    pub fn add_transaction(env: &Env, user_id: Symbol, group_id: u64, amount: u64, asset: Address, attachments: Vec<Attachment>, split: Split) -> u64 {
+        let group = Self::load_group(env, group_id);
+        if !group.members.contains(&user_id) {
+            panic_with_error!(env, Error::MemberNotFound);
+        }
+        if !group.assets.contains(&asset) {
+            panic_with_error!(env, Error::AssetNotAccepted);
+        }
+        Self::require_permission(env, &group, &Self::get_member_address(env, &user_id), Action::PostExpense);
+
+        let payers = Map::from_array(env, [(user_id.clone(), amount)]);
+        Self::post_expense(env, &group, user_id, payers, asset, attachments, &split)
+    }
+
+    // Like `add_transaction` for a bill paid by several members. `user_id`
+    // posts the expense and need not be one of the payers.
+    pub fn add_multi_payer_transaction(env: &Env, user_id: Symbol, group_id: u64, payers: Map<Symbol, u64>, asset: Address, attachments: Vec<Attachment>, split: Split) -> u64 {
+        let group = Self::load_group(env, group_id);
+        if !group.members.contains(&user_id) {
+            panic_with_error!(env, Error::MemberNotFound);
+        }
+        if !group.assets.contains(&asset) {
+            panic_with_error!(env, Error::AssetNotAccepted);
+        }
+        Self::require_permission(env, &group, &Self::get_member_address(env, &user_id), Action::PostExpense);
+
+        if payers.is_empty() {
+            panic_with_error!(env, Error::InvalidAmount);
+        }
+        for (payer, paid) in payers.iter() {
+            if !group.members.contains(&payer) {
+                panic_with_error!(env, Error::MemberNotFound);
+            }
+            if paid == 0 {
+                panic_with_error!(env, Error::InvalidAmount);
+            }
+        }
+
+        Self::post_expense(env, &group, user_id, payers, asset, attachments, &split)
+    }
+
+    // Each item is split between its members, then the bill total, after tax,
+    // tip and discount, is allocated in proportion to the item subtotals.
+    pub fn add_itemized_transaction(env: &Env, user_id: Symbol, group_id: u64, asset: Address, attachments: Vec<Attachment>, bill: Bill) -> u64 {
+        let group = Self::load_group(env, group_id);
+        if !group.members.contains(&user_id) {
+            panic_with_error!(env, Error::MemberNotFound);
+        }
+        if !group.assets.contains(&asset) {
+            panic_with_error!(env, Error::AssetNotAccepted);
+        }
+        Self::require_permission(env, &group, &Self::get_member_address(env, &user_id), Action::PostExpense);
+
+        if bill.items.is_empty() {
+            panic_with_error!(env, Error::InvalidSplit);
+        }
+        // The id post_expense is about to assign, so round-robin remainders line up
+        let tx_id = env.storage().persistent().get::<DataKey, u64>(&DataKey::LastTransactionId).unwrap_or(0) + 1;
+        let mut subtotals: Map<Symbol, u64> = Map::new(env);
+        let mut items_total: u64 = 0;
+        for item in bill.items.iter() {
+            let cost = item.price.checked_mul(item.quantity as u64)
+                .unwrap_or_else(|| panic_with_error!(env, Error::InvalidAmount));
+            if cost == 0 {
+                continue;
+            }
+            let shares = Self::compute_shares(env, &group, &user_id, cost, &Split::Equal(item.members), tx_id);
+            for (member, share) in shares.iter() {
+                subtotals.set(member.clone(), subtotals.get(member).unwrap_or(0) + share);
+            }
+            items_total = items_total.checked_add(cost).unwrap_or_else(|| panic_with_error!(env, Error::InvalidAmount));
+        }
+
+        let total = items_total.checked_add(bill.tax)
+            .and_then(|total| total.checked_add(bill.tip))
+            .and_then(|total| total.checked_sub(bill.discount))
+            .filter(|total| *total > 0)
+            .unwrap_or_else(|| panic_with_error!(env, Error::InvalidAmount));
+        let shares = Self::proportional(env, &group, &user_id, total, &subtotals, tx_id);
+
+        let payers = Map::from_array(env, [(user_id.clone(), total)]);
+        let tx_id = Self::post_expense(env, &group, user_id, payers, asset, attachments, &Split::Exact(shares));
+        Self::save(env, &DataKey::Bill(tx_id), &bill);
+        tx_id
+    }
+
+    pub fn get_bill(env: &Env, tx_id: u64) -> Bill {
+        env.storage().persistent().get::<DataKey, Bill>(&DataKey::Bill(tx_id))
+            .unwrap_or_else(|| panic_with_error!(env, Error::TransactionNotFound))
+    }
+
+    pub fn get_transaction(env: &Env, tx_id: u64) -> Transaction {
+        let key = DataKey::Transaction(tx_id);
+        let transaction = env.storage().persistent().get::<DataKey, Transaction>(&key)
+            .unwrap_or_else(|| panic_with_error!(env, Error::TransactionNotFound));
+        Self::touch(env, &key);
+        transaction
+    }
+
+    // Only the payer may add proofs to an expense after posting it
+    pub fn attach(env: &Env, tx_id: u64, attachment: Attachment) {
+        let mut transaction = Self::get_transaction(env, tx_id);
+        let payer = Self::get_member_address(env, &transaction.user_id);
+        payer.require_auth();
+
+        transaction.attachments.push_back(attachment.clone());
+        Self::check_attachments(env, &transaction.attachments);
+        Self::save(env, &DataKey::Transaction(tx_id), &transaction);
+        Self::publish(env, "attachment_added", transaction.group_id, &payer, (tx_id, attachment.hash));
+    }
+
+    // Replaces the terms of a pending expense the author paid alone. An
+    // expense with several payers has to be cancelled and posted again.
+    // Votes cast so far were for the old terms, so the approval round
+    // starts over.
+    pub fn edit_transaction(env: &Env, tx_id: u64, amount: u64, asset: Address, split: Split) {
+        let mut transaction = Self::get_transaction(env, tx_id);
+        let group = Self::load_group(env, transaction.group_id);
+        let author = Self::get_member_address(env, &transaction.user_id);
+        Self::require_permission(env, &group, &author, Action::PostExpense);
+
+        if transaction.status != TxStatus::Pending {
+            panic_with_error!(env, Error::TransactionNotPending);
+        }
+        if transaction.reverses != 0 {
+            panic_with_error!(env, Error::InvalidReversal);
+        }
+        if transaction.payers.len() != 1 || !transaction.payers.contains_key(transaction.user_id.clone()) {
+            panic_with_error!(env, Error::MultiplePayers);
+        }
+        if !group.assets.contains(&asset) {
+            panic_with_error!(env, Error::AssetNotAccepted);
+        }
+
+        // The conversion is redone and the bill no longer matches the amounts
+        env.storage().persistent().remove(&DataKey::Conversion(tx_id));
+        env.storage().persistent().remove(&DataKey::Bill(tx_id));
+        let payers = Map::from_array(env, [(transaction.user_id.clone(), amount)]);
+        let (amount, asset, payers, shares) = Self::price(env, &group, tx_id, &transaction.user_id, payers, asset, &split);
+        transaction.amount = amount;
+        transaction.asset = asset;
+        transaction.payers = payers;
+        transaction.shares = shares;
+        transaction.approvals = Vec::from_array(env, [transaction.user_id.clone()]);
+        transaction.rejections = Vec::new(env);
+        transaction.created_ledger = env.ledger().sequence();
+        Self::tally(env, &group, &mut transaction);
+        Self::save(env, &DataKey::Transaction(tx_id), &transaction);
+
+        Self::publish(env, "expense_edited", group.group_id, &author, (tx_id, transaction.amount, transaction.asset.clone()));
+        Self::publish_outcome(env, &transaction, &author);
+    }
+
+    pub fn cancel_transaction(env: &Env, tx_id: u64) {
+        let mut transaction = Self::get_transaction(env, tx_id);
+        let author = Self::get_member_address(env, &transaction.user_id);
+        author.require_auth();
+
+        if transaction.status != TxStatus::Pending {
+            panic_with_error!(env, Error::TransactionNotPending);
+        }
+        transaction.status = TxStatus::Cancelled;
+        Self::save(env, &DataKey::Transaction(tx_id), &transaction);
+        Self::publish_outcome(env, &transaction, &author);
+    }
+
+    // Posts an entry that undoes an approved expense once it collects the
+    // approvals an expense needs. The original stays in the history and is
+    // marked reversed at that point.
+    pub fn reverse_transaction(env: &Env, tx_id: u64) -> u64 {
+        let original = Self::get_transaction(env, tx_id);
+        let group = Self::load_group(env, original.group_id);
+        let author = Self::get_member_address(env, &original.user_id);
+        Self::require_permission(env, &group, &author, Action::PostExpense);
+
+        if original.status != TxStatus::Approved || original.reverses != 0 {
+            panic_with_error!(env, Error::InvalidReversal);
+        }
+        // A rejected or expired reversal may be retried, a pending one may not
+        if let Some(reversal_id) = env.storage().persistent().get::<DataKey, u64>(&DataKey::Reversal(tx_id)) {
+            if Self::get_transaction(env, reversal_id).status == TxStatus::Pending {
+                panic_with_error!(env, Error::InvalidReversal);
+            }
+        }
+
+        let reversal_id = Self::get_next_transaction_id(env);
+        Self::save(env, &DataKey::Reversal(tx_id), &reversal_id);
+        let reversal = Transaction {
+            tx_id: reversal_id,
+            user_id: original.user_id.clone(),
+            group_id: original.group_id,
+            amount: original.amount,
+            asset: original.asset,
+            attachments: Vec::new(env),
+            approvals: Vec::from_array(env, [original.user_id]),
+            payers: original.payers,
+            shares: original.shares,
+            rejections: Vec::new(env),
+            status: TxStatus::Pending,
+            created_ledger: env.ledger().sequence(),
+            reverses: tx_id,
+        };
+        Self::record(env, &group, reversal)
+    }
+
+    // True when `receipt` hashes to one of the expense's attachments
+    pub fn verify_receipt(env: &Env, tx_id: u64, receipt: Bytes) -> bool {
+        let transaction = Self::get_transaction(env, tx_id);
+        let hash = env.crypto().sha256(&receipt).to_bytes();
+        transaction.attachments.iter().any(|attachment| attachment.hash == hash)
     }
-}
\ No newline at end of file
+
+    // The payer authorizes every future occurrence up front, after that
+    // anyone may `trigger` an occurrence once it is due
+    #[allow(clippy::too_many_arguments)]
+    pub fn add_recurring(env: &Env, user_id: Symbol, group_id: u64, amount: u64, asset: Address, attachments: Vec<Attachment>, split: Split, interval: Interval, first_due: u64, end: u64) -> u64 {
+        let group = Self::load_group(env, group_id);
+        if !group.members.contains(&user_id) {
+            panic_with_error!(env, Error::MemberNotFound);
+        }
+        if !group.assets.contains(&asset) {
+            panic_with_error!(env, Error::AssetNotAccepted);
+        }
+        Self::require_permission(env, &group, &Self::get_member_address(env, &user_id), Action::PostExpense);
+
+        if matches!(interval, Interval::Ledgers(0) | Interval::Seconds(0)) || (end != 0 && end < first_due) {
+            panic_with_error!(env, Error::InvalidSchedule);
+        }
+        // Fails early on a split or attachments that could never be posted
+        Self::compute_shares(env, &group, &user_id, amount, &split, 0);
+        Self::check_attachments(env, &attachments);
+
+        let recurring_id = env.storage().persistent().get::<DataKey, u64>(&DataKey::LastRecurringId).unwrap_or(0) + 1;
+        Self::save(env, &DataKey::LastRecurringId, &recurring_id);
+
+        let recurring = Recurring {
+            recurring_id,
+            group_id,
+            payer: user_id,
+            amount,
+            asset,
+            attachments,
+            split,
+            interval,
+            next_due: first_due,
+            end,
+            status: RecurringStatus::Active,
+        };
+        Self::save(env, &DataKey::Recurring(recurring_id), &recurring);
+        Self::track(env, &DataKey::GroupRecurring(group_id), recurring_id);
+        Self::publish(env, "recurring_added", group_id, &Self::get_member_address(env, &recurring.payer), recurring_id);
+        recurring_id
+    }
+
+    pub fn get_recurring(env: &Env, recurring_id: u64) -> Recurring {
+        let key = DataKey::Recurring(recurring_id);
+        let recurring = env.storage().persistent().get::<DataKey, Recurring>(&key)
+            .unwrap_or_else(|| panic_with_error!(env, Error::RecurringNotFound));
+        Self::touch(env, &key);
+        recurring
+    }
+
+    // Posts the occurrence that is due and moves the schedule one interval
+    // on. Missed occurrences are caught up one trigger at a time.
+    pub fn trigger(env: &Env, recurring_id: u64) -> u64 {
+        let mut recurring = Self::get_recurring(env, recurring_id);
+        if recurring.status != RecurringStatus::Active {
+            panic_with_error!(env, Error::InvalidSchedule);
+        }
+
+        let (now, step) = match recurring.interval {
+            Interval::Ledgers(ledgers) => (env.ledger().sequence() as u64, ledgers as u64),
+            Interval::Seconds(seconds) => (env.ledger().timestamp(), seconds),
+        };
+        if now < recurring.next_due {
+            panic_with_error!(env, Error::NotDue);
+        }
+
+        let group = Self::load_group(env, recurring.group_id);
+        if !group.members.contains(&recurring.payer) {
+            panic_with_error!(env, Error::MemberNotFound);
+        }
+        let payers = Map::from_array(env, [(recurring.payer.clone(), recurring.amount)]);
+        let tx_id = Self::post_expense(env, &group, recurring.payer.clone(), payers, recurring.asset.clone(), recurring.attachments.clone(), &recurring.split);
+
+        recurring.next_due += step;
+        if recurring.end != 0 && recurring.next_due > recurring.end {
+            recurring.status = RecurringStatus::Finished;
+            Self::untrack(env, &DataKey::GroupRecurring(recurring.group_id), recurring_id);
+            Self::publish(env, "recurring_finished", recurring.group_id, &Self::get_member_address(env, &recurring.payer), recurring_id);
+        }
+        Self::save(env, &DataKey::Recurring(recurring_id), &recurring);
+        tx_id
+    }
+
+    pub fn pause_recurring(env: &Env, recurring_id: u64, caller: Address) {
+        Self::set_recurring_status(env, recurring_id, &[RecurringStatus::Active], RecurringStatus::Paused, caller);
+    }
+
+    pub fn resume_recurring(env: &Env, recurring_id: u64, caller: Address) {
+        Self::set_recurring_status(env, recurring_id, &[RecurringStatus::Paused], RecurringStatus::Active, caller);
+    }
+
+    pub fn cancel_recurring(env: &Env, recurring_id: u64, caller: Address) {
+        Self::set_recurring_status(env, recurring_id, &[RecurringStatus::Active, RecurringStatus::Paused], RecurringStatus::Cancelled, caller);
+    }
+
+    // Both sides sign: the lender pays out the principal, the borrower agrees
+    // to the total due, which is fixed when the loan is made
+    #[allow(clippy::too_many_arguments)]
+    pub fn create_loan(env: &Env, group_id: u64, lender: Symbol, borrower: Symbol, asset: Address, principal: i128, interest_bps: u32, due: u64) -> u64 {
+        let group = Self::load_group(env, group_id);
+        if lender == borrower || !group.members.contains(&lender) || !group.members.contains(&borrower) {
+            panic_with_error!(env, Error::MemberNotFound);
+        }
+        if !group.assets.contains(&asset) {
+            panic_with_error!(env, Error::AssetNotAccepted);
+        }
+        if principal <= 0 {
+            panic_with_error!(env, Error::InvalidAmount);
+        }
+        let start = env.ledger().timestamp();
+        if due <= start {
+            panic_with_error!(env, Error::InvalidSchedule);
+        }
+
+        let lender_address = Self::get_member_address(env, &lender);
+        let borrower_address = Self::get_member_address(env, &borrower);
+        lender_address.require_auth();
+        borrower_address.require_auth();
+        token::Client::new(env, &asset).transfer(&lender_address, &borrower_address, &principal);
+
+        let interest = principal * interest_bps as i128 * (due - start) as i128 / (10_000 * SECONDS_PER_YEAR as i128);
+        let loan_id = env.storage().persistent().get::<DataKey, u64>(&DataKey::LastLoanId).unwrap_or(0) + 1;
+        Self::save(env, &DataKey::LastLoanId, &loan_id);
+
+        let loan = Loan {
+            loan_id,
+            group_id,
+            lender,
+            borrower,
+            asset,
+            principal,
+            interest_bps,
+            start,
+            due,
+            total_due: principal + interest,
+            repaid: 0,
+            status: LoanStatus::Open,
+        };
+        Self::save(env, &DataKey::Loan(loan_id), &loan);
+        Self::track(env, &DataKey::GroupLoans(group_id), loan_id);
+        Self::publish(env, "loan_created", group_id, &lender_address, (loan_id, principal));
+        loan_id
+    }
+
+    // An open loan reads as overdue once its due date has passed
+    pub fn get_loan(env: &Env, loan_id: u64) -> Loan {
+        let key = DataKey::Loan(loan_id);
+        let mut loan = env.storage().persistent().get::<DataKey, Loan>(&key)
+            .unwrap_or_else(|| panic_with_error!(env, Error::LoanNotFound));
+        Self::touch(env, &key);
+        if loan.status == LoanStatus::Open && env.ledger().timestamp() > loan.due {
+            loan.status = LoanStatus::Overdue;
+        }
+        loan
+    }
+
+    // Repays up to `amount`, never more than what is still outstanding
+    pub fn repay_loan(env: &Env, loan_id: u64, amount: i128) -> LoanStatus {
+        let mut loan = Self::get_loan(env, loan_id);
+        if !matches!(loan.status, LoanStatus::Open | LoanStatus::Overdue) {
+            panic_with_error!(env, Error::LoanClosed);
+        }
+        if amount <= 0 {
+            panic_with_error!(env, Error::InvalidAmount);
+        }
+
+        let borrower_address = Self::get_member_address(env, &loan.borrower);
+        borrower_address.require_auth();
+        let payment = amount.min(loan.total_due - loan.repaid);
+        token::Client::new(env, &loan.asset).transfer(&borrower_address, &Self::get_member_address(env, &loan.lender), &payment);
+
+        loan.repaid += payment;
+        if loan.repaid == loan.total_due {
+            loan.status = LoanStatus::Repaid;
+            Self::untrack(env, &DataKey::GroupLoans(loan.group_id), loan_id);
+        }
+        Self::save(env, &DataKey::Loan(loan_id), &loan);
+        Self::publish(env, "loan_repaid", loan.group_id, &borrower_address, (loan_id, payment));
+        loan.status
+    }
+
+    pub fn forgive_loan(env: &Env, loan_id: u64) {
+        let mut loan = Self::get_loan(env, loan_id);
+        if !matches!(loan.status, LoanStatus::Open | LoanStatus::Overdue) {
+            panic_with_error!(env, Error::LoanClosed);
+        }
+        let lender_address = Self::get_member_address(env, &loan.lender);
+        lender_address.require_auth();
+
+        loan.status = LoanStatus::Forgiven;
+        Self::save(env, &DataKey::Loan(loan_id), &loan);
+        Self::untrack(env, &DataKey::GroupLoans(loan.group_id), loan_id);
+        Self::publish(env, "loan_forgiven", loan.group_id, &lender_address, loan_id);
+    }
+
+    // Shares the next transaction of the group would record for this split
+    pub fn preview_split(env: &Env, group_id: u64, user_id: Symbol, amount: u64, split: Split) -> Map<Symbol, u64> {
+        let group = Self::load_group(env, group_id);
+        let next_tx_id = env.storage().persistent().get::<DataKey, u64>(&DataKey::LastTransactionId).unwrap_or(0) + 1;
+        Self::compute_shares(env, &group, &user_id, amount, &split, next_tx_id)
+    }
+
+    pub fn get_balance(env: &Env, group_id: u64, user_id: Symbol, asset: Address) -> i128 {
+        env.storage().persistent().get::<DataKey, i128>(&DataKey::Position(group_id, user_id, asset)).unwrap_or(0)
+    }
+
+    // Transfers that bring every net position in `asset` back to zero.
+    // Debtors and creditors are paired largest first, which needs at most
+    // one transfer fewer than there are members with a non-zero position.
+    pub fn suggest_settlements(env: &Env, group_id: u64, asset: Address) -> Vec<Settlement> {
+        let group = Self::load_group(env, group_id);
+
+        let mut debtors: Vec<(Symbol, i128)> = Vec::new(env);
+        let mut creditors: Vec<(Symbol, i128)> = Vec::new(env);
+        for user_id in group.members.iter() {
+            let balance = Self::get_balance(env, group_id, user_id.clone(), asset.clone());
+            if balance < 0 {
+                debtors.push_back((user_id, -balance));
+            } else if balance > 0 {
+                creditors.push_back((user_id, balance));
+            }
+        }
+
+        let mut plan = Vec::new(env);
+        while let (Some(d), Some(c)) = (Self::largest(&debtors), Self::largest(&creditors)) {
+            let (from, owed) = debtors.get_unchecked(d);
+            let (to, due) = creditors.get_unchecked(c);
+            let amount = owed.min(due);
+            plan.push_back(Settlement { from: from.clone(), to: to.clone(), amount });
+            debtors.set(d, (from, owed - amount));
+            creditors.set(c, (to, due - amount));
+        }
+        plan
+    }
+
+    // Executes `suggest_settlements` through the asset's token contract;
+    // every debtor in the plan has to authorize their transfer. Guests have
+    // no address to pay from or to, so transfers involving one are left out
+    // and only the executed ones are returned.
+    pub fn settle_all(env: &Env, group_id: u64, asset: Address) -> Vec<Settlement> {
+        let mut executed = Vec::new(env);
+        for settlement in Self::suggest_settlements(env, group_id, asset.clone()).iter() {
+            if Self::member_address(env, &settlement.from).is_none() || Self::member_address(env, &settlement.to).is_none() {
+                continue;
+            }
+            Self::transfer_settlement(env, group_id, &asset, &settlement);
+            executed.push_back(settlement);
+        }
+        executed
+    }
+
+    // Moves tokens from the member into the group treasury held by this contract
+    pub fn deposit(env: &Env, group_id: u64, user_id: Symbol, asset: Address, amount: i128) {
+        let group = Self::load_group(env, group_id);
+        if !group.members.contains(&user_id) {
+            panic_with_error!(env, Error::MemberNotFound);
+        }
+        if !group.assets.contains(&asset) {
+            panic_with_error!(env, Error::AssetNotAccepted);
+        }
+        if amount <= 0 {
+            panic_with_error!(env, Error::InvalidAmount);
+        }
+
+        let address = Self::get_member_address(env, &user_id);
+        address.require_auth();
+        token::Client::new(env, &asset).transfer(&address, &env.current_contract_address(), &amount);
+
+        let treasury = Self::treasury_balance(env, group_id, asset.clone());
+        Self::save(env, &DataKey::Treasury(group_id, asset.clone()), &(treasury + amount));
+
+        let deposited = Self::get_deposit(env, group_id, user_id.clone(), asset.clone());
+        Self::save(env, &DataKey::Deposit(group_id, user_id.clone(), asset.clone()), &(deposited + amount));
+        if deposited == 0 {
+            let key = DataKey::Depositors(group_id, asset.clone());
+            let mut depositors = env.storage().persistent().get::<DataKey, Vec<Symbol>>(&key).unwrap_or_else(|| Vec::new(env));
+            depositors.push_back(user_id);
+            Self::save(env, &key, &depositors);
+        }
+        Self::publish(env, "deposited", group_id, &address, (asset, amount));
+    }
+
+    pub fn treasury_balance(env: &Env, group_id: u64, asset: Address) -> i128 {
+        env.storage().persistent().get::<DataKey, i128>(&DataKey::Treasury(group_id, asset)).unwrap_or(0)
+    }
+
+    pub fn get_deposit(env: &Env, group_id: u64, user_id: Symbol, asset: Address) -> i128 {
+        env.storage().persistent().get::<DataKey, i128>(&DataKey::Deposit(group_id, user_id, asset)).unwrap_or(0)
+    }
+
+    // Proposes paying `amount` from the treasury to `to`, typically a
+    // merchant. It follows the same quorum and approval window as expenses,
+    // but always needs the approval of someone besides the proposer.
+    pub fn propose_withdrawal(env: &Env, group_id: u64, user_id: Symbol, asset: Address, to: Address, amount: i128) -> u64 {
+        let group = Self::load_group(env, group_id);
+        if !group.members.contains(&user_id) {
+            panic_with_error!(env, Error::MemberNotFound);
+        }
+        let proposer = Self::get_member_address(env, &user_id);
+        Self::require_permission(env, &group, &proposer, Action::PostExpense);
+
+        if !group.assets.contains(&asset) {
+            panic_with_error!(env, Error::AssetNotAccepted);
+        }
+        if amount <= 0 {
+            panic_with_error!(env, Error::InvalidAmount);
+        }
+        if amount > Self::treasury_balance(env, group_id, asset.clone()) {
+            panic_with_error!(env, Error::InsufficientTreasury);
+        }
+
+        let withdrawal_id = env.storage().persistent().get::<DataKey, u64>(&DataKey::LastWithdrawalId).unwrap_or(0) + 1;
+        Self::save(env, &DataKey::LastWithdrawalId, &withdrawal_id);
+
+        let mut withdrawal = Withdrawal {
+            withdrawal_id,
+            group_id,
+            proposer: user_id.clone(),
+            asset,
+            to,
+            amount,
+            approvals: Vec::from_array(env, [user_id]),
+            rejections: Vec::new(env),
+            status: TxStatus::Pending,
+            created_ledger: env.ledger().sequence(),
+        };
+        Self::tally_withdrawal(env, &group, &mut withdrawal);
+        Self::save(env, &DataKey::Withdrawal(withdrawal_id), &withdrawal);
+        if withdrawal.status == TxStatus::Pending {
+            Self::track(env, &DataKey::GroupWithdrawals(group_id), withdrawal_id);
+        }
+
+        Self::publish(env, "withdrawal_proposed", group_id, &proposer, (withdrawal_id, withdrawal.amount));
+        Self::publish_withdrawal_outcome(env, &withdrawal, &proposer);
+        withdrawal_id
+    }
+
+    pub fn get_withdrawal(env: &Env, withdrawal_id: u64) -> Withdrawal {
+        let key = DataKey::Withdrawal(withdrawal_id);
+        let withdrawal = env.storage().persistent().get::<DataKey, Withdrawal>(&key)
+            .unwrap_or_else(|| panic_with_error!(env, Error::WithdrawalNotFound));
+        Self::touch(env, &key);
+        withdrawal
+    }
+
+    pub fn approve_withdrawal(env: &Env, withdrawal_id: u64, approver_id: Symbol) -> TxStatus {
+        Self::vote_withdrawal(env, withdrawal_id, approver_id, true)
+    }
+
+    pub fn reject_withdrawal(env: &Env, withdrawal_id: u64, member_id: Symbol) -> TxStatus {
+        Self::vote_withdrawal(env, withdrawal_id, member_id, false)
+    }
+
+    // Refunds whatever is left in the treasury to the depositors, pro-rata to
+    // what each of them put in
+    pub fn close_treasury(env: &Env, group_id: u64, caller: Address) {
+        let group = Self::load_group(env, group_id);
+
+        Self::require_permission(env, &group, &caller, Action::CloseGroup);
+
+        for asset in group.assets.iter() {
+            Self::refund_treasury(env, group_id, &asset);
+        }
+        Self::publish(env, "treasury_closed", group_id, &caller, ());
+    }
+
+    // Settles every position, through token transfers when `settle` is set,
+    // refunds the treasury and replaces the group with a `GroupSummary`.
+    // Guests cannot settle, their positions have to be reassigned or the
+    // guest claimed first. Transactions are no longer extended and expire
+    // with their TTL.
+    pub fn close_group(env: &Env, group_id: u64, settle: bool, caller: Address) {
+        let group = Self::load_group(env, group_id);
+
+        Self::require_permission(env, &group, &caller, Action::CloseGroup);
+
+        for asset in group.assets.iter() {
+            if settle {
+                Self::settle_all(env, group_id, asset.clone());
+            }
+            for user_id in group.members.iter() {
+                if Self::get_balance(env, group_id, user_id.clone(), asset.clone()) != 0 && Self::member_address(env, &user_id).is_none() {
+                    panic_with_error!(env, Error::GuestCannotSettle);
+                }
+            }
+            for user_id in group.members.iter() {
+                if Self::get_balance(env, group_id, user_id, asset.clone()) != 0 {
+                    panic_with_error!(env, Error::OutstandingBalance);
+                }
+            }
+            Self::refund_treasury(env, group_id, &asset);
+        }
+
+        for user_id in group.members.iter() {
+            for asset in group.assets.iter() {
+                env.storage().persistent().remove(&DataKey::Position(group_id, user_id.clone(), asset));
+            }
+            match Self::member_address(env, &user_id) {
+                Some(address) => {
+                    env.storage().persistent().remove(&DataKey::Role(group_id, address.clone()));
+                    Self::unindex_membership(env, &address, group_id);
+                }
+                None => {
+                    env.storage().persistent().remove(&DataKey::Guest(user_id.clone()));
+                    env.storage().persistent().remove(&DataKey::GuestClaim(user_id));
+                }
+            }
+        }
+        let transaction_count = Self::transaction_count(env, group_id);
+        for page in 0..=transaction_count / INDEX_PAGE_SIZE {
+            env.storage().persistent().remove(&DataKey::GroupTxPage(group_id, page));
+        }
+        for key in [
+            DataKey::Group(group_id),
+            DataKey::PendingOwner(group_id),
+            DataKey::InviteCode(group_id),
+            DataKey::Oracle(group_id),
+            DataKey::GroupTxCount(group_id),
+            DataKey::GroupWithdrawals(group_id),
+            DataKey::GroupRecurring(group_id),
+            DataKey::GroupLoans(group_id),
+            DataKey::LastBump(group_id),
+        ] {
+            env.storage().persistent().remove(&key);
+        }
+
+        let summary = GroupSummary {
+            group_id,
+            owner: group.owner,
+            members: group.members,
+            totals: group.totals,
+            transaction_count,
+            closed_at: env.ledger().timestamp(),
+        };
+        Self::save(env, &DataKey::Summary(group_id), &summary);
+        Self::publish(env, "group_closed", group_id, &caller, transaction_count);
+    }
+
+    pub fn get_summary(env: &Env, group_id: u64) -> GroupSummary {
+        let key = DataKey::Summary(group_id);
+        let summary = env.storage().persistent().get::<DataKey, GroupSummary>(&key)
+            .unwrap_or_else(|| panic_with_error!(env, Error::GroupNotFound));
+        Self::touch(env, &key);
+        summary
+    }
+
+    pub fn approve_transaction(env: &Env, tx_id: u64, approver_id: Symbol) -> TxStatus {
+        Self::vote(env, tx_id, approver_id, true)
+    }
+
+    pub fn reject_transaction(env: &Env, tx_id: u64, member_id: Symbol) -> TxStatus {
+        Self::vote(env, tx_id, member_id, false)
+    }
+
+    // Lets anyone close a pending expense whose approval window has passed
+    pub fn expire_transaction(env: &Env, tx_id: u64) {
+        let mut transaction = Self::get_transaction(env, tx_id);
+        let group = Self::load_group(env, transaction.group_id);
+
+        if transaction.status != TxStatus::Pending {
+            panic_with_error!(env, Error::TransactionNotPending);
+        }
+        if !Self::is_expired(env, &group, transaction.created_ledger) {
+            panic_with_error!(env, Error::NotExpired);
+        }
+
+        transaction.status = TxStatus::Expired;
+        Self::save(env, &DataKey::Transaction(tx_id), &transaction);
+        Self::publish_outcome(env, &transaction, &Self::get_member_address(env, &transaction.user_id));
+    }
+
+    // Extends the contract, the group and every entry kept under it:
+    // members, roles, positions, the treasury, pending withdrawals, live
+    // recurring expenses and loans, and the transaction index. The
+    // transactions themselves grow without bound, see `bump_transactions`.
+    pub fn bump_group(env: &Env, group_id: u64) {
+        let group = Self::load_group(env, group_id);
+        env.storage().instance().extend_ttl(BUMP_AMOUNT, BUMP_AMOUNT);
+
+        for key in [
+            DataKey::Group(group_id),
+            DataKey::PendingOwner(group_id),
+            DataKey::InviteCode(group_id),
+            DataKey::Oracle(group_id),
+            DataKey::GroupTxCount(group_id),
+        ] {
+            Self::bump(env, &key);
+        }
+        for page in 0..=Self::transaction_count(env, group_id) / INDEX_PAGE_SIZE {
+            Self::bump(env, &DataKey::GroupTxPage(group_id, page));
+        }
+
+        for (index_key, entry_key) in [
+            (DataKey::GroupWithdrawals(group_id), DataKey::Withdrawal as fn(u64) -> DataKey),
+            (DataKey::GroupRecurring(group_id), DataKey::Recurring),
+            (DataKey::GroupLoans(group_id), DataKey::Loan),
+        ] {
+            Self::bump(env, &index_key);
+            let ids = env.storage().persistent().get::<DataKey, Vec<u64>>(&index_key).unwrap_or_else(|| Vec::new(env));
+            for id in ids.iter() {
+                Self::bump(env, &entry_key(id));
+            }
+        }
+
+        for asset in group.assets.iter() {
+            Self::bump(env, &DataKey::Treasury(group_id, asset.clone()));
+            let depositors_key = DataKey::Depositors(group_id, asset.clone());
+            Self::bump(env, &depositors_key);
+            let depositors = env.storage().persistent().get::<DataKey, Vec<Symbol>>(&depositors_key).unwrap_or_else(|| Vec::new(env));
+            for user_id in depositors.iter() {
+                Self::bump(env, &DataKey::Deposit(group_id, user_id, asset.clone()));
+            }
+        }
+
+        for user_id in group.members.iter() {
+            for asset in group.assets.iter() {
+                Self::bump(env, &DataKey::Position(group_id, user_id.clone(), asset));
+            }
+            let Some(address) = Self::member_address(env, &user_id) else {
+                Self::bump(env, &DataKey::Guest(user_id.clone()));
+                Self::bump(env, &DataKey::GuestClaim(user_id));
+                continue;
+            };
+            Self::bump(env, &DataKey::Member(user_id.clone()));
+            Self::bump(env, &DataKey::Role(group_id, address.clone()));
+            Self::bump(env, &DataKey::MembershipCount(address.clone()));
+            let pos_key = DataKey::MembershipPos(address.clone(), group_id);
+            if let Some(position) = env.storage().persistent().get::<DataKey, u32>(&pos_key) {
+                Self::bump(env, &pos_key);
+                Self::bump(env, &DataKey::Membership(address, position));
+            }
+        }
+
+        Self::save(env, &DataKey::LastBump(group_id), &env.ledger().sequence());
+    }
+
+    // Extends the transactions at positions [cursor, cursor + limit) of the
+    // group, together with their conversion and bill records
+    pub fn bump_transactions(env: &Env, group_id: u64, cursor: u32, limit: u32) {
+        for transaction in Self::list_transactions(env, group_id, cursor, limit).iter() {
+            Self::bump(env, &DataKey::Transaction(transaction.tx_id));
+            Self::bump(env, &DataKey::Conversion(transaction.tx_id));
+            Self::bump(env, &DataKey::Bill(transaction.tx_id));
+            Self::bump(env, &DataKey::Reversal(transaction.tx_id));
+        }
+    }
+
+    // Ledgers left before the entries `bump_group` covers can be archived,
+    // counted from its last run or from the creation of the group. Entries
+    // written since then live at least as long.
+    pub fn group_ttl(env: &Env, group_id: u64) -> u32 {
+        let bumped = env.storage().persistent().get::<DataKey, u32>(&DataKey::LastBump(group_id))
+            .unwrap_or_else(|| panic_with_error!(env, Error::GroupNotFound));
+        (bumped + BUMP_AMOUNT).saturating_sub(env.ledger().sequence())
+    }
+
+    pub fn group_count(env: &Env) -> u64 {
+        env.storage().persistent().get::<DataKey, u64>(&DataKey::LastGroupId).unwrap_or(0)
+    }
+
+    // Groups with ids in (cursor, cursor + limit]. Ids are sequential, so the
+    // next page starts at cursor + limit until it reaches `group_count`.
+    pub fn list_groups(env: &Env, cursor: u64, limit: u32) -> Vec<Group> {
+        let last_id = Self::group_count(env);
+        let end = cursor.saturating_add(limit.min(MAX_PAGE_SIZE) as u64).min(last_id);
+        let mut groups = Vec::new(env);
+        for group_id in cursor + 1..=end {
+            if let Some(group) = env.storage().persistent().get::<DataKey, Group>(&DataKey::Group(group_id)) {
+                groups.push_back(group);
+            }
+        }
+        groups
+    }
+
+    // Ids of the groups `address` is a member of. Leaving a group moves the
+    // last entry into the freed position, so the order is not stable.
+    pub fn groups_of(env: &Env, address: Address, cursor: u32, limit: u32) -> Vec<u64> {
+        let count = env.storage().persistent().get::<DataKey, u32>(&DataKey::MembershipCount(address.clone())).unwrap_or(0);
+        let end = cursor.saturating_add(limit.min(MAX_PAGE_SIZE)).min(count);
+        let mut group_ids = Vec::new(env);
+        for position in cursor..end {
+            let group_id = env.storage().persistent().get::<DataKey, u64>(&DataKey::Membership(address.clone(), position))
+                .unwrap_or_else(|| panic_with_error!(env, Error::GroupNotFound));
+            group_ids.push_back(group_id);
+        }
+        group_ids
+    }
+
+    pub fn transaction_count(env: &Env, group_id: u64) -> u32 {
+        env.storage().persistent().get::<DataKey, u32>(&DataKey::GroupTxCount(group_id)).unwrap_or(0)
+    }
+
+    // Transactions of the group in posting order, starting at position `cursor`
+    pub fn list_transactions(env: &Env, group_id: u64, cursor: u32, limit: u32) -> Vec<Transaction> {
+        let end = cursor.saturating_add(limit.min(MAX_PAGE_SIZE)).min(Self::transaction_count(env, group_id));
+        let mut transactions = Vec::new(env);
+        let mut page: Vec<u64> = Vec::new(env);
+        for position in cursor..end {
+            if position == cursor || position % INDEX_PAGE_SIZE == 0 {
+                page = env.storage().persistent().get::<DataKey, Vec<u64>>(&DataKey::GroupTxPage(group_id, position / INDEX_PAGE_SIZE))
+                    .unwrap_or_else(|| Vec::new(env));
+            }
+            let tx_id = page.get_unchecked(position % INDEX_PAGE_SIZE);
+            transactions.push_back(Self::get_transaction(env, tx_id));
+        }
+        transactions
+    }
+
+    fn load_group(env: &Env, group_id: u64) -> Group {
+        let key = DataKey::Group(group_id);
+        let group = env.storage().persistent().get::<DataKey, Group>(&key).unwrap_or_else(|| {
+            if env.storage().persistent().has(&DataKey::Summary(group_id)) {
+                panic_with_error!(env, Error::GroupClosed);
+            }
+            panic_with_error!(env, Error::GroupNotFound)
+        });
+        Self::touch(env, &key);
+        group
+    }
+
+    fn save<V: IntoVal<Env, Val>>(env: &Env, key: &DataKey, value: &V) {
+        env.storage().persistent().set(key, value);
+        Self::touch(env, key);
+    }
+
+    fn touch(env: &Env, key: &DataKey) {
+        env.storage().persistent().extend_ttl(key, BUMP_THRESHOLD, BUMP_AMOUNT);
+    }
+
+    // Unlike `touch` this always extends to the full BUMP_AMOUNT, which is
+    // what lets `group_ttl` count from the last bump
+    fn bump(env: &Env, key: &DataKey) {
+        if env.storage().persistent().has(key) {
+            env.storage().persistent().extend_ttl(key, BUMP_AMOUNT, BUMP_AMOUNT);
+        }
+    }
+
+    fn get_next_group_id(env: &Env) -> u64 {
+        let last_id = env.storage().persistent().get::<DataKey, u64>(&DataKey::LastGroupId).unwrap_or(0);
+        let new_id = last_id.checked_add(1).unwrap_or_else(|| panic_with_error!(env, Error::GroupCreationFailed));
+        Self::save(env, &DataKey::LastGroupId, &new_id);
+        new_id
+    }
+
+    // Topics are the event name, the group and the address that acted, so
+    // the dapp can subscribe per group or per user
+    fn publish<T: IntoVal<Env, Val>>(env: &Env, name: &str, group_id: u64, actor: &Address, data: T) {
+        env.events().publish((Symbol::new(env, name), group_id, actor.clone()), data);
+    }
+
+    // Announces an expense that a vote or the approval window has closed
+    fn publish_outcome(env: &Env, transaction: &Transaction, actor: &Address) {
+        let name = match transaction.status {
+            TxStatus::Pending => return,
+            TxStatus::Approved => "expense_approved",
+            TxStatus::Rejected => "expense_rejected",
+            TxStatus::Expired => "expense_expired",
+            TxStatus::Cancelled => "expense_cancelled",
+            TxStatus::Reversed => "expense_reversed",
+        };
+        Self::publish(env, name, transaction.group_id, actor, transaction.tx_id);
+    }
+
+    fn publish_withdrawal_outcome(env: &Env, withdrawal: &Withdrawal, actor: &Address) {
+        let name = match withdrawal.status {
+            TxStatus::Pending | TxStatus::Cancelled | TxStatus::Reversed => return,
+            TxStatus::Approved => "withdrawal_paid",
+            TxStatus::Rejected => "withdrawal_rejected",
+            TxStatus::Expired => "withdrawal_expired",
+        };
+        Self::publish(env, name, withdrawal.group_id, actor, withdrawal.withdrawal_id);
+    }
+
+    fn get_next_transaction_id(env: &Env) -> u64 {
+        let last_id = env.storage().persistent().get::<DataKey, u64>(&DataKey::LastTransactionId).unwrap_or(0);
+        let new_id = last_id.checked_add(1).unwrap_or_else(|| panic_with_error!(env, Error::GroupCreationFailed));
+        Self::save(env, &DataKey::LastTransactionId, &new_id);
+        new_id
+    }
+
+    // Records an expense on behalf of `user_id`, whose authorization the
+    // caller has already checked
+    fn post_expense(env: &Env, group: &Group, user_id: Symbol, payers: Map<Symbol, u64>, asset: Address, attachments: Vec<Attachment>, split: &Split) -> u64 {
+        Self::check_attachments(env, &attachments);
+        let tx_id = Self::get_next_transaction_id(env);
+        let (amount, asset, payers, shares) = Self::price(env, group, tx_id, &user_id, payers, asset, split);
+        let transaction = Transaction {
+            tx_id,
+            user_id: user_id.clone(),
+            group_id: group.group_id,
+            amount,
+            asset,
+            attachments,
+            approvals: Vec::from_array(env, [user_id]),
+            payers,
+            shares,
+            rejections: Vec::new(env),
+            status: TxStatus::Pending,
+            created_ledger: env.ledger().sequence(),
+            reverses: 0,
+        };
+        Self::record(env, group, transaction)
+    }
+
+    // Converts what the payers put in into the group base asset when an
+    // oracle is set and splits the total. Returns the amount, asset,
+    // contributions and shares to record.
+    #[allow(clippy::too_many_arguments, clippy::type_complexity)]
+    fn price(env: &Env, group: &Group, tx_id: u64, user_id: &Symbol, payers: Map<Symbol, u64>, asset: Address, split: &Split) -> (u64, Address, Map<Symbol, u64>, Map<Symbol, u64>) {
+        let amount = payers.values().iter()
+            .try_fold(0u64, |total, paid| total.checked_add(paid))
+            .unwrap_or_else(|| panic_with_error!(env, Error::InvalidAmount));
+        let (converted, asset) = match env.storage().persistent().get::<DataKey, OracleConfig>(&DataKey::Oracle(group.group_id)) {
+            Some(config) if config.base_asset != asset => {
+                let conversion = Self::convert(env, &config, asset, amount);
+                let converted = amount as i128 * conversion.rate / 10i128.pow(conversion.decimals);
+                Self::save(env, &DataKey::Conversion(tx_id), &conversion);
+                (u64::try_from(converted).unwrap_or_else(|_| panic_with_error!(env, Error::InvalidAmount)), config.base_asset)
+            }
+            _ => (amount, asset),
+        };
+        // Exact amounts are given in the original asset and scaled to the converted total
+        let shares = match split {
+            Split::Exact(_) if converted != amount => {
+                let exact = Self::compute_shares(env, group, user_id, amount, split, tx_id);
+                Self::proportional(env, group, user_id, converted, &exact, tx_id)
+            }
+            _ => Self::compute_shares(env, group, user_id, converted, split, tx_id),
+        };
+        // Rounding goes to the largest payer, the poster may not have paid at all
+        let payers = if converted != amount {
+            let mut largest = user_id.clone();
+            let mut largest_paid = 0;
+            for (payer, paid) in payers.iter() {
+                if paid > largest_paid {
+                    largest = payer;
+                    largest_paid = paid;
+                }
+            }
+            Self::proportional(env, group, &largest, converted, &payers, tx_id)
+        } else {
+            payers
+        };
+        (converted, asset, payers, shares)
+    }
+
+    // Counts the author's own approval, then stores and indexes a new entry
+    fn record(env: &Env, group: &Group, mut transaction: Transaction) -> u64 {
+        let tx_id = transaction.tx_id;
+        Self::tally(env, group, &mut transaction);
+        Self::save(env, &DataKey::Transaction(tx_id), &transaction);
+
+        let payer = Self::get_member_address(env, &transaction.user_id);
+        Self::publish(env, "expense_added", group.group_id, &payer, (tx_id, transaction.amount, transaction.asset.clone()));
+        Self::publish_outcome(env, &transaction, &payer);
+
+        // Index the transaction under its group
+        let count = env.storage().persistent().get::<DataKey, u32>(&DataKey::GroupTxCount(group.group_id)).unwrap_or(0);
+        let page_key = DataKey::GroupTxPage(group.group_id, count / INDEX_PAGE_SIZE);
+        let mut page = env.storage().persistent().get::<DataKey, Vec<u64>>(&page_key).unwrap_or_else(|| Vec::new(env));
+        page.push_back(tx_id);
+        Self::save(env, &page_key, &page);
+        Self::save(env, &DataKey::GroupTxCount(group.group_id), &(count + 1));
+
+        tx_id
+    }
+
+    fn track(env: &Env, key: &DataKey, id: u64) {
+        let mut ids = env.storage().persistent().get::<DataKey, Vec<u64>>(key).unwrap_or_else(|| Vec::new(env));
+        ids.push_back(id);
+        Self::save(env, key, &ids);
+    }
+
+    fn untrack(env: &Env, key: &DataKey, id: u64) {
+        let Some(mut ids) = env.storage().persistent().get::<DataKey, Vec<u64>>(key) else {
+            return;
+        };
+        if let Some(index) = ids.first_index_of(id) {
+            ids.remove(index);
+            Self::save(env, key, &ids);
+        }
+    }
+
+    fn check_attachments(env: &Env, attachments: &Vec<Attachment>) {
+        if attachments.len() > MAX_ATTACHMENTS {
+            panic_with_error!(env, Error::InvalidAttachment);
+        }
+        for attachment in attachments.iter() {
+            if attachment.uri.is_empty() || attachment.uri.len() > MAX_URI_LEN || attachment.mime.len() > MAX_MIME_LEN {
+                panic_with_error!(env, Error::InvalidAttachment);
+            }
+        }
+    }
+
+    fn set_recurring_status(env: &Env, recurring_id: u64, from: &[RecurringStatus], to: RecurringStatus, caller: Address) {
+        let mut recurring = Self::get_recurring(env, recurring_id);
+        let group = Self::load_group(env, recurring.group_id);
+
+        Self::require_permission(env, &group, &caller, Action::ManageRecurring);
+
+        if !from.contains(&recurring.status) {
+            panic_with_error!(env, Error::InvalidSchedule);
+        }
+        recurring.status = to;
+        Self::save(env, &DataKey::Recurring(recurring_id), &recurring);
+        if to == RecurringStatus::Cancelled {
+            Self::untrack(env, &DataKey::GroupRecurring(recurring.group_id), recurring_id);
+        }
+
+        let name = match to {
+            RecurringStatus::Active => "recurring_resumed",
+            RecurringStatus::Paused => "recurring_paused",
+            RecurringStatus::Cancelled => "recurring_cancelled",
+            RecurringStatus::Finished => "recurring_finished",
+        };
+        Self::publish(env, name, recurring.group_id, &caller, recurring_id);
+    }
+
+    fn enroll(env: &Env, mut group: Group, user_id: Symbol) {
+        if group.members.contains(&user_id) {
+            panic_with_error!(env, Error::MemberAlreadyExists);
+        }
+
+        let member = env.storage().persistent().get::<DataKey, Member>(&DataKey::Member(user_id.clone()))
+            .unwrap_or_else(|| panic_with_error!(env, Error::MemberNotFound));
+
+        Self::index_membership(env, &member.address, group.group_id);
+        group.members.push_back(user_id.clone());
+        Self::save(env, &DataKey::Group(group.group_id), &group);
+
+        Self::publish(env, "member_joined", group.group_id, &member.address, user_id);
+    }
+
+    // An address holds at most one member id per group, otherwise it could
+    // vote once per id
+    fn index_membership(env: &Env, address: &Address, group_id: u64) {
+        if env.storage().persistent().has(&DataKey::MembershipPos(address.clone(), group_id)) {
+            panic_with_error!(env, Error::MemberAlreadyExists);
+        }
+        let count = env.storage().persistent().get::<DataKey, u32>(&DataKey::MembershipCount(address.clone())).unwrap_or(0);
+        Self::save(env, &DataKey::Membership(address.clone(), count), &group_id);
+        Self::save(env, &DataKey::MembershipPos(address.clone(), group_id), &count);
+        Self::save(env, &DataKey::MembershipCount(address.clone()), &(count + 1));
+    }
+
+    fn unindex_membership(env: &Env, address: &Address, group_id: u64) {
+        let pos_key = DataKey::MembershipPos(address.clone(), group_id);
+        let Some(position) = env.storage().persistent().get::<DataKey, u32>(&pos_key) else {
+            return;
+        };
+        let last = env.storage().persistent().get::<DataKey, u32>(&DataKey::MembershipCount(address.clone())).unwrap_or(1) - 1;
+        if position != last {
+            let moved = env.storage().persistent().get::<DataKey, u64>(&DataKey::Membership(address.clone(), last))
+                .unwrap_or_else(|| panic_with_error!(env, Error::GroupNotFound));
+            Self::save(env, &DataKey::Membership(address.clone(), position), &moved);
+            Self::save(env, &DataKey::MembershipPos(address.clone(), moved), &position);
+        }
+        env.storage().persistent().remove(&DataKey::Membership(address.clone(), last));
+        env.storage().persistent().remove(&pos_key);
+        Self::save(env, &DataKey::MembershipCount(address.clone()), &last);
+    }
+
+    // Clears the member's positions as requested, then drops them from the
+    // group and the group from their membership index. Recurring expenses
+    // the member pays could never be triggered again and are cancelled.
+    fn detach_member(env: &Env, mut group: Group, user_id: Symbol, resolution: Option<Resolution>) {
+        let group_id = group.group_id;
+        let index = group.members.first_index_of(&user_id)
+            .unwrap_or_else(|| panic_with_error!(env, Error::MemberNotFound));
+
+        for asset in group.assets.iter() {
+            let balance = Self::get_balance(env, group_id, user_id.clone(), asset.clone());
+            if balance != 0 {
+                match &resolution {
+                    None => panic_with_error!(env, Error::OutstandingBalance),
+                    Some(Resolution::Reassign(other)) => {
+                        if *other == user_id || !group.members.contains(other) {
+                            panic_with_error!(env, Error::MemberNotFound);
+                        }
+                        Self::get_member_address(env, other).require_auth();
+                        Self::adjust_balance(env, group_id, other, &asset, balance);
+                        Self::adjust_balance(env, group_id, &user_id, &asset, -balance);
+                    }
+                    Some(Resolution::Settle) => Self::settle_position(env, &group, &user_id, &asset, balance),
+                }
+            }
+            env.storage().persistent().remove(&DataKey::Position(group_id, user_id.clone(), asset));
+        }
+
+        group.members.remove(index);
+        Self::save(env, &DataKey::Group(group_id), &group);
+
+        let actor = Self::member_address(env, &user_id).unwrap_or_else(|| group.owner.clone());
+        let recurring_ids = env.storage().persistent().get::<DataKey, Vec<u64>>(&DataKey::GroupRecurring(group_id)).unwrap_or_else(|| Vec::new(env));
+        for recurring_id in recurring_ids.iter() {
+            let mut recurring = Self::get_recurring(env, recurring_id);
+            if recurring.payer == user_id {
+                recurring.status = RecurringStatus::Cancelled;
+                Self::save(env, &DataKey::Recurring(recurring_id), &recurring);
+                Self::untrack(env, &DataKey::GroupRecurring(group_id), recurring_id);
+                Self::publish(env, "recurring_cancelled", group_id, &actor, recurring_id);
+            }
+        }
+
+        match Self::member_address(env, &user_id) {
+            Some(address) => {
+                env.storage().persistent().remove(&DataKey::Role(group_id, address.clone()));
+                Self::unindex_membership(env, &address, group_id);
+                Self::publish(env, "member_left", group_id, &address, user_id);
+            }
+            None => {
+                env.storage().persistent().remove(&DataKey::Guest(user_id.clone()));
+                env.storage().persistent().remove(&DataKey::GuestClaim(user_id.clone()));
+                Self::publish(env, "member_left", group_id, &group.owner, user_id);
+            }
+        }
+    }
+
+    // Settles one member's position through token transfers with the
+    // members on the other side, largest counter-position first. Positions
+    // in an asset sum to zero, so the other side covers it in full unless
+    // part of it sits with guests.
+    fn settle_position(env: &Env, group: &Group, user_id: &Symbol, asset: &Address, balance: i128) {
+        if Self::member_address(env, user_id).is_none() {
+            panic_with_error!(env, Error::GuestCannotSettle);
+        }
+        let mut remaining = balance.abs();
+        while remaining > 0 {
+            let mut counterparty: Option<(Symbol, i128)> = None;
+            for other in group.members.iter() {
+                if Self::member_address(env, &other).is_none() {
+                    continue;
+                }
+                let other_balance = Self::get_balance(env, group.group_id, other.clone(), asset.clone());
+                let available = if balance > 0 { -other_balance } else { other_balance };
+                if available > counterparty.as_ref().map_or(0, |(_, best)| *best) {
+                    counterparty = Some((other, available));
+                }
+            }
+            let (other, available) = counterparty
+                .unwrap_or_else(|| panic_with_error!(env, Error::OutstandingBalance));
+            let amount = remaining.min(available);
+            let settlement = if balance > 0 {
+                Settlement { from: other, to: user_id.clone(), amount }
+            } else {
+                Settlement { from: user_id.clone(), to: other, amount }
+            };
+            Self::transfer_settlement(env, group.group_id, asset, &settlement);
+            remaining -= amount;
+        }
+    }
+
+    fn transfer_settlement(env: &Env, group_id: u64, asset: &Address, settlement: &Settlement) {
+        let from = Self::get_member_address(env, &settlement.from);
+        let to = Self::get_member_address(env, &settlement.to);
+        from.require_auth();
+        token::Client::new(env, asset).transfer(&from, &to, &settlement.amount);
+        Self::adjust_balance(env, group_id, &settlement.from, asset, settlement.amount);
+        Self::adjust_balance(env, group_id, &settlement.to, asset, -settlement.amount);
+        Self::publish(env, "settled", group_id, &from, (asset.clone(), settlement.clone()));
+    }
+
+    fn allows(role: Role, action: Action) -> bool {
+        match role {
+            Role::Owner => true,
+            Role::Admin => action != Action::CloseGroup,
+            Role::Member => matches!(action, Action::PostExpense | Action::Approve),
+            Role::Viewer => false,
+        }
+    }
+
+    // Role of an address in the group. Members without a stored role are
+    // plain members, so this only answers for them when `address` belongs
+    // to one of `group.members`.
+    fn role_of(env: &Env, group: &Group, address: &Address) -> Option<Role> {
+        if *address == group.owner {
+            return Some(Role::Owner);
+        }
+        if let Some(role) = env.storage().persistent().get::<DataKey, Role>(&DataKey::Role(group.group_id, address.clone())) {
+            return Some(role);
+        }
+        group.members.iter()
+            .any(|user_id| Self::member_address(env, &user_id).as_ref() == Some(address))
+            .then_some(Role::Member)
+    }
+
+    fn require_permission(env: &Env, group: &Group, caller: &Address, action: Action) {
+        caller.require_auth();
+        if !Self::role_of(env, group, caller).is_some_and(|role| Self::allows(role, action)) {
+            panic_with_error!(env, Error::Unauthorized);
+        }
+    }
+
+    fn vote(env: &Env, tx_id: u64, member_id: Symbol, approve: bool) -> TxStatus {
+        let mut transaction = Self::get_transaction(env, tx_id);
+        let group = Self::load_group(env, transaction.group_id);
+
+        if Self::cast_vote(env, &group, member_id.clone(), approve, transaction.status, transaction.created_ledger, &mut transaction.approvals, &mut transaction.rejections) {
+            Self::tally(env, &group, &mut transaction);
+        } else {
+            transaction.status = TxStatus::Expired;
+        }
+        Self::save(env, &DataKey::Transaction(tx_id), &transaction);
+        Self::publish_outcome(env, &transaction, &Self::get_member_address(env, &member_id));
+        transaction.status
+    }
+
+    // Records a member's vote on a pending expense or withdrawal. Returns
+    // false when the approval window has passed: a late vote does not
+    // count, it only records that the window closed.
+    #[allow(clippy::too_many_arguments)]
+    fn cast_vote(env: &Env, group: &Group, member_id: Symbol, approve: bool, status: TxStatus, created_ledger: u32, approvals: &mut Vec<Symbol>, rejections: &mut Vec<Symbol>) -> bool {
+        if !group.members.contains(&member_id) {
+            panic_with_error!(env, Error::Unauthorized);
+        }
+        let address = Self::get_member_address(env, &member_id);
+        Self::require_permission(env, group, &address, Action::Approve);
+
+        if status != TxStatus::Pending {
+            panic_with_error!(env, Error::TransactionNotPending);
+        }
+        // One vote per signer, whichever of its ids cast it
+        let voted = approvals.iter().chain(rejections.iter())
+            .any(|voter| voter == member_id || Self::member_address(env, &voter).as_ref() == Some(&address));
+        if voted {
+            panic_with_error!(env, Error::AlreadyVoted);
+        }
+
+        if Self::is_expired(env, group, created_ledger) {
+            return false;
+        }
+        if approve {
+            approvals.push_back(member_id);
+        } else {
+            rejections.push_back(member_id);
+        }
+        true
+    }
+
+    fn vote_withdrawal(env: &Env, withdrawal_id: u64, member_id: Symbol, approve: bool) -> TxStatus {
+        let mut withdrawal = Self::get_withdrawal(env, withdrawal_id);
+        let group = Self::load_group(env, withdrawal.group_id);
+
+        if Self::cast_vote(env, &group, member_id.clone(), approve, withdrawal.status, withdrawal.created_ledger, &mut withdrawal.approvals, &mut withdrawal.rejections) {
+            Self::tally_withdrawal(env, &group, &mut withdrawal);
+        } else {
+            withdrawal.status = TxStatus::Expired;
+        }
+        Self::save(env, &DataKey::Withdrawal(withdrawal_id), &withdrawal);
+        if withdrawal.status != TxStatus::Pending {
+            Self::untrack(env, &DataKey::GroupWithdrawals(group.group_id), withdrawal_id);
+        }
+        Self::publish_withdrawal_outcome(env, &withdrawal, &Self::get_member_address(env, &member_id));
+        withdrawal.status
+    }
+
+    fn tally_withdrawal(env: &Env, group: &Group, withdrawal: &mut Withdrawal) {
+        withdrawal.status = Self::outcome(env, group, &withdrawal.approvals, &withdrawal.rejections);
+        // The proposer's own approval comes first and votes are one per
+        // signer, so a second approval is always someone else's
+        if withdrawal.status == TxStatus::Approved && withdrawal.approvals.len() < 2 {
+            withdrawal.status = TxStatus::Pending;
+        }
+        if withdrawal.status != TxStatus::Approved {
+            return;
+        }
+
+        let key = DataKey::Treasury(group.group_id, withdrawal.asset.clone());
+        let treasury = env.storage().persistent().get::<DataKey, i128>(&key).unwrap_or(0);
+        if withdrawal.amount > treasury {
+            panic_with_error!(env, Error::InsufficientTreasury);
+        }
+        Self::save(env, &key, &(treasury - withdrawal.amount));
+        token::Client::new(env, &withdrawal.asset).transfer(&env.current_contract_address(), &withdrawal.to, &withdrawal.amount);
+    }
+
+    // Pays the leftover out in proportion to each deposit. The stroops lost
+    // to rounding go to the largest depositor, the first one on ties.
+    fn refund_treasury(env: &Env, group_id: u64, asset: &Address) {
+        let treasury_key = DataKey::Treasury(group_id, asset.clone());
+        let depositors_key = DataKey::Depositors(group_id, asset.clone());
+        let leftover = env.storage().persistent().get::<DataKey, i128>(&treasury_key).unwrap_or(0);
+        let depositors = env.storage().persistent().get::<DataKey, Vec<Symbol>>(&depositors_key).unwrap_or_else(|| Vec::new(env));
+
+        let mut deposits: Vec<i128> = Vec::new(env);
+        let mut total: i128 = 0;
+        let mut largest = 0;
+        for (i, user_id) in depositors.iter().enumerate() {
+            let deposited = Self::get_deposit(env, group_id, user_id, asset.clone());
+            if deposited > deposits.get(largest).unwrap_or(0) {
+                largest = i as u32;
+            }
+            deposits.push_back(deposited);
+            total += deposited;
+        }
+
+        if leftover > 0 && total > 0 {
+            let mut refunds: Vec<i128> = Vec::new(env);
+            let mut refunded = 0;
+            for deposited in deposits.iter() {
+                let refund = leftover * deposited / total;
+                refunds.push_back(refund);
+                refunded += refund;
+            }
+            refunds.set(largest, refunds.get_unchecked(largest) + leftover - refunded);
+
+            let token_client = token::Client::new(env, asset);
+            for (i, user_id) in depositors.iter().enumerate() {
+                let refund = refunds.get_unchecked(i as u32);
+                if refund > 0 {
+                    token_client.transfer(&env.current_contract_address(), &Self::get_member_address(env, &user_id), &refund);
+                }
+            }
+        }
+
+        for user_id in depositors.iter() {
+            env.storage().persistent().remove(&DataKey::Deposit(group_id, user_id, asset.clone()));
+        }
+        env.storage().persistent().remove(&depositors_key);
+        env.storage().persistent().remove(&treasury_key);
+    }
+
+    // Distinct addresses allowed to vote, viewers do not count towards the
+    // quorum
+    fn voter_count(env: &Env, group: &Group) -> u32 {
+        let mut voters: Vec<Address> = Vec::new(env);
+        for user_id in group.members.iter() {
+            // Guests cannot sign, so they do not vote either
+            let Some(address) = Self::member_address(env, &user_id) else {
+                continue;
+            };
+            let role = if address == group.owner {
+                Role::Owner
+            } else {
+                env.storage().persistent().get::<DataKey, Role>(&DataKey::Role(group.group_id, address.clone()))
+                    .unwrap_or(Role::Member)
+            };
+            if Self::allows(role, Action::Approve) && !voters.contains(&address) {
+                voters.push_back(address);
+            }
+        }
+        voters.len()
+    }
+
+    fn required_approvals(voters: u32, quorum: Quorum) -> u32 {
+        let required = match quorum {
+            Quorum::Count(count) => count.min(voters),
+            Quorum::Percent(percent) => (voters * percent).div_ceil(100),
+        };
+        required.max(1)
+    }
+
+    fn is_expired(env: &Env, group: &Group, created_ledger: u32) -> bool {
+        group.approval_window > 0
+            && env.ledger().sequence() > created_ledger.saturating_add(group.approval_window)
+    }
+
+    // Approved once enough members approve, rejected once the members who
+    // have not rejected can no longer reach the quorum
+    fn outcome(env: &Env, group: &Group, approvals: &Vec<Symbol>, rejections: &Vec<Symbol>) -> TxStatus {
+        let voters = Self::voter_count(env, group);
+        let required = Self::required_approvals(voters, group.quorum);
+        if approvals.len() >= required {
+            TxStatus::Approved
+        } else if voters.saturating_sub(rejections.len()) < required {
+            TxStatus::Rejected
+        } else {
+            TxStatus::Pending
+        }
+    }
+
+    // Settles the expense status after a vote. Balances only change on
+    // approval.
+    fn tally(env: &Env, group: &Group, transaction: &mut Transaction) {
+        let parties_present = group.members.contains(&transaction.user_id)
+            && transaction.payers.keys().iter().all(|payer| group.members.contains(&payer))
+            && transaction.shares.keys().iter().all(|participant| group.members.contains(&participant));
+        transaction.status = if parties_present {
+            Self::outcome(env, group, &transaction.approvals, &transaction.rejections)
+        } else {
+            // Someone it would charge or credit has left the group since
+            TxStatus::Rejected
+        };
+
+        if transaction.status == TxStatus::Approved {
+            // A reversal applies the effects of the original with the sign flipped
+            let sign: i128 = if transaction.reverses == 0 { 1 } else { -1 };
+
+            let mut group = group.clone();
+            let total = group.totals.get(transaction.asset.clone()).unwrap_or(0);
+            let total = if sign > 0 { total + transaction.amount } else { total.saturating_sub(transaction.amount) };
+            group.totals.set(transaction.asset.clone(), total);
+            Self::save(env, &DataKey::Group(group.group_id), &group);
+
+            // Credit every payer's contribution and debit every participant's share
+            for (payer, paid) in transaction.payers.iter() {
+                Self::adjust_balance(env, group.group_id, &payer, &transaction.asset, sign * paid as i128);
+            }
+            for (participant, share) in transaction.shares.iter() {
+                Self::adjust_balance(env, group.group_id, &participant, &transaction.asset, -sign * share as i128);
+            }
+
+            if transaction.reverses != 0 {
+                let mut original = Self::get_transaction(env, transaction.reverses);
+                original.status = TxStatus::Reversed;
+                Self::save(env, &DataKey::Transaction(original.tx_id), &original);
+                Self::publish_outcome(env, &original, &Self::get_member_address(env, &original.user_id));
+            }
+        }
+    }
+
+    // Both prices are quoted in the oracle's own base, so their ratio is the
+    // price of `asset` in the group base asset. Amounts are in the smallest
+    // unit of each token, so the rate also covers any difference between the
+    // token decimals: the scale grows when `asset` has more of them, the
+    // rate when the base asset does.
+    fn convert(env: &Env, config: &OracleConfig, asset: Address, amount: u64) -> Conversion {
+        let oracle = PriceOracleClient::new(env, &config.oracle);
+        let asset_price = Self::fresh_price(env, config, &oracle, asset.clone());
+        let base_price = Self::fresh_price(env, config, &oracle, config.base_asset.clone());
+
+        let asset_decimals = token::Client::new(env, &asset).decimals();
+        let base_decimals = token::Client::new(env, &config.base_asset).decimals();
+        let oracle_decimals = oracle.decimals();
+        let rate = asset_price.price * 10i128.pow(oracle_decimals) / base_price.price
+            * 10i128.pow(base_decimals.saturating_sub(asset_decimals));
+        let decimals = oracle_decimals + asset_decimals.saturating_sub(base_decimals);
+        Conversion {
+            asset,
+            amount,
+            rate,
+            decimals,
+            timestamp: asset_price.timestamp.min(base_price.timestamp),
+        }
+    }
+
+    fn fresh_price(env: &Env, config: &OracleConfig, oracle: &PriceOracleClient, asset: Address) -> PriceData {
+        let price = oracle.lastprice(&OracleAsset::Stellar(asset))
+            .unwrap_or_else(|| panic_with_error!(env, Error::PriceUnavailable));
+        if price.price <= 0 {
+            panic_with_error!(env, Error::PriceUnavailable);
+        }
+        if env.ledger().timestamp().saturating_sub(price.timestamp) > config.max_age {
+            panic_with_error!(env, Error::StalePrice);
+        }
+        price
+    }
+
+    // Checks that the registry, if any, has `user_id` registered to `owner`,
+    // or not registered at all for None
+    fn check_handle(env: &Env, user_id: &Symbol, owner: Option<&Address>) {
+        let Some(registry) = env.storage().instance().get::<DataKey, Address>(&DataKey::Registry) else {
+            return;
+        };
+        env.storage().instance().extend_ttl(BUMP_THRESHOLD, BUMP_AMOUNT);
+        if RegistryClient::new(env, &registry).owner_of(user_id).as_ref() != owner {
+            panic_with_error!(env, Error::HandleNotOwned);
+        }
+    }
+
+    // None for a guest
+    fn member_address(env: &Env, user_id: &Symbol) -> Option<Address> {
+        env.storage().persistent().get::<DataKey, Member>(&DataKey::Member(user_id.clone())).map(|member| member.address)
+    }
+
+    fn get_member_address(env: &Env, user_id: &Symbol) -> Address {
+        let key = DataKey::Member(user_id.clone());
+        let member = env.storage().persistent().get::<DataKey, Member>(&key)
+            .unwrap_or_else(|| panic_with_error!(env, Error::MemberNotFound));
+        Self::touch(env, &key);
+        member.address
+    }
+
+    // Index of the entry with the largest positive amount, first one on ties
+    fn largest(entries: &Vec<(Symbol, i128)>) -> Option<u32> {
+        let mut best: Option<u32> = None;
+        let mut best_amount = 0;
+        for (i, (_, amount)) in entries.iter().enumerate() {
+            if amount > best_amount {
+                best = Some(i as u32);
+                best_amount = amount;
+            }
+        }
+        best
+    }
+
+    fn adjust_balance(env: &Env, group_id: u64, user_id: &Symbol, asset: &Address, delta: i128) {
+        let key = DataKey::Position(group_id, user_id.clone(), asset.clone());
+        let new_balance = env.storage().persistent().get::<DataKey, i128>(&key).unwrap_or(0) + delta;
+        Self::save(env, &key, &new_balance);
+    }
+
+    // Scales the given amounts to a new total, e.g. an exact split after
+    // currency conversion or item subtotals after tax, tip and discount
+    fn proportional(env: &Env, group: &Group, payer: &Symbol, amount: u64, amounts: &Map<Symbol, u64>, tx_id: u64) -> Map<Symbol, u64> {
+        let mut participants: Vec<Symbol> = Vec::new(env);
+        let mut weights: Vec<u64> = Vec::new(env);
+        for member in group.members.iter() {
+            if let Some(weight) = amounts.get(member.clone()).filter(|weight| *weight > 0) {
+                participants.push_back(member);
+                weights.push_back(weight);
+            }
+        }
+        Self::allocate(env, group, payer, amount, &participants, &weights, tx_id)
+    }
+
+    // Divides `amount` according to `split`. Participants are always visited in
+    // group member order so the remainder lands on the same members no matter
+    // how the caller ordered its input.
+    fn compute_shares(env: &Env, group: &Group, payer: &Symbol, amount: u64, split: &Split, tx_id: u64) -> Map<Symbol, u64> {
+        if amount == 0 {
+            panic_with_error!(env, Error::InvalidAmount);
+        }
+
+        let mut participants: Vec<Symbol> = Vec::new(env);
+        let mut weights: Vec<u64> = Vec::new(env);
+        match split {
+            Split::Equal(listed) => {
+                for member in group.members.iter() {
+                    if listed.is_empty() || listed.contains(&member) {
+                        participants.push_back(member);
+                        weights.push_back(1);
+                    }
+                }
+                if !listed.is_empty() && participants.len() != listed.len() {
+                    panic_with_error!(env, Error::InvalidSplit);
+                }
+            }
+            Split::Shares(listed) => {
+                for member in group.members.iter() {
+                    if let Some(weight) = listed.get(member.clone()) {
+                        if weight == 0 {
+                            panic_with_error!(env, Error::InvalidSplit);
+                        }
+                        participants.push_back(member);
+                        weights.push_back(weight as u64);
+                    }
+                }
+                if participants.len() != listed.len() {
+                    panic_with_error!(env, Error::InvalidSplit);
+                }
+            }
+            Split::Exact(listed) => {
+                let mut total: u64 = 0;
+                for (member, share) in listed.iter() {
+                    if !group.members.contains(&member) {
+                        panic_with_error!(env, Error::InvalidSplit);
+                    }
+                    total = total.checked_add(share).unwrap_or_else(|| panic_with_error!(env, Error::InvalidAmount));
+                }
+                if total != amount {
+                    panic_with_error!(env, Error::InvalidSplit);
+                }
+                return listed.clone();
+            }
+        }
+        Self::allocate(env, group, payer, amount, &participants, &weights, tx_id)
+    }
+
+    // Divides `amount` between `participants` in proportion to `weights`,
+    // handing out the remainder according to the group policy
+    fn allocate(env: &Env, group: &Group, payer: &Symbol, amount: u64, participants: &Vec<Symbol>, weights: &Vec<u64>, tx_id: u64) -> Map<Symbol, u64> {
+        if participants.is_empty() {
+            panic_with_error!(env, Error::InvalidSplit);
+        }
+
+        let total_weight: u128 = weights.iter().map(|w| w as u128).sum();
+        let mut shares: Vec<u64> = Vec::new(env);
+        let mut fractions: Vec<u128> = Vec::new(env);
+        let mut allocated: u64 = 0;
+        for weight in weights.iter() {
+            let scaled = amount as u128 * weight as u128;
+            let share = (scaled / total_weight) as u64;
+            shares.push_back(share);
+            fractions.push_back(scaled % total_weight);
+            allocated += share;
+        }
+
+        // Each floored share loses less than one stroop, so the remainder is
+        // always smaller than the number of participants.
+        let remainder = amount - allocated;
+        let count = participants.len();
+        let mut result: Map<Symbol, u64> = Map::new(env);
+        match group.remainder_policy {
+            RemainderPolicy::Payer => {
+                for i in 0..count {
+                    result.set(participants.get_unchecked(i), shares.get_unchecked(i));
+                }
+                if remainder > 0 {
+                    let current = result.get(payer.clone()).unwrap_or(0);
+                    result.set(payer.clone(), current + remainder);
+                }
+                return result;
+            }
+            RemainderPolicy::RoundRobin => {
+                let start = (tx_id % count as u64) as u32;
+                for k in 0..remainder as u32 {
+                    let i = (start + k) % count;
+                    shares.set(i, shares.get_unchecked(i) + 1);
+                }
+            }
+            RemainderPolicy::LargestRemainder => {
+                for _ in 0..remainder {
+                    let mut best = 0;
+                    for i in 1..count {
+                        if fractions.get_unchecked(i) > fractions.get_unchecked(best) {
+                            best = i;
+                        }
+                    }
+                    shares.set(best, shares.get_unchecked(best) + 1);
+                    fractions.set(best, 0);
+                }
+            }
+        }
+
+        for i in 0..count {
+            result.set(participants.get_unchecked(i), shares.get_unchecked(i));
+        }
+        result
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use soroban_sdk::{symbol_short, testutils::{storage::{Instance as _, Persistent as _}, Address as _, Events, Ledger}, FromVal, token::{StellarAssetClient, TokenClient}};
+
+    struct Setup<'a> {
+        client: MappingContractClient<'a>,
+        owner: Address,
+        group_id: u64,
+        addresses: Vec<Address>,
+        token: Address,
+    }
+
+    // Group of alice, bob and carol, each holding 1000 of the group's token
+    fn setup(env: &Env) -> Setup<'_> {
+        env.mock_all_auths();
+        let client = MappingContractClient::new(env, &env.register_contract(None, MappingContract));
+        let owner = Address::generate(env);
+        let token = env.register_stellar_asset_contract_v2(Address::generate(env)).address();
+        let group_id = client.create_group(&owner);
+        client.add_asset(&group_id, &token, &owner);
+        let mut addresses = Vec::new(env);
+        for user_id in [symbol_short!("alice"), symbol_short!("bob"), symbol_short!("carol")] {
+            let address = Address::generate(env);
+            client.create_member(&user_id, &address);
+            client.add_member_to_group(&group_id, &user_id, &owner);
+            StellarAssetClient::new(env, &token).mint(&address, &1_000);
+            addresses.push_back(address);
+        }
+        Setup { client, owner, group_id, addresses, token }
+    }
+
+    #[contract]
+    struct MockOracle;
+
+    #[contractimpl]
+    impl MockOracle {
+        pub fn set_price(env: Env, asset: Address, price: i128, timestamp: u64) {
+            env.storage().instance().set(&asset, &PriceData { price, timestamp });
+        }
+
+        pub fn decimals(_env: Env) -> u32 {
+            14
+        }
+
+        pub fn lastprice(env: Env, asset: OracleAsset) -> Option<PriceData> {
+            match asset {
+                OracleAsset::Stellar(address) => env.storage().instance().get(&address),
+                OracleAsset::Other(_) => None,
+            }
+        }
+    }
+
+    // Only what a conversion reads from a token that is not a Stellar asset.
+    // A module of its own, as MockOracle exports a `decimals` too.
+    mod mock_token {
+        use soroban_sdk::{contract, contractimpl, Env};
+
+        #[contract]
+        pub struct MockToken;
+
+        #[contractimpl]
+        impl MockToken {
+            pub fn decimals(_env: Env) -> u32 {
+                9
+            }
+        }
+    }
+
+    #[contract]
+    struct MockRegistry;
+
+    #[contractimpl]
+    impl MockRegistry {
+        pub fn register(env: Env, handle: Symbol, owner: Address) {
+            env.storage().instance().set(&handle, &owner);
+        }
+
+        pub fn owner_of(env: Env, handle: Symbol) -> Option<Address> {
+            env.storage().instance().get(&handle)
+        }
+    }
+
+    fn equal(env: &Env) -> Split {
+        Split::Equal(Vec::new(env))
+    }
+
+    #[test]
+    fn test_payer_absorbs_remainder() {
+        let env = Env::default();
+        let Setup { client, group_id, token, .. } = setup(&env);
+
+        client.add_transaction(&symbol_short!("bob"), &group_id, &100, &token, &Vec::new(&env), &equal(&env));
+
+        assert_eq!(client.get_balance(&group_id, &symbol_short!("alice"), &token), -33);
+        assert_eq!(client.get_balance(&group_id, &symbol_short!("bob"), &token), 66);
+        assert_eq!(client.get_balance(&group_id, &symbol_short!("carol"), &token), -33);
+    }
+
+    #[test]
+    fn test_round_robin_rotates_with_transaction_id() {
+        let env = Env::default();
+        let Setup { client, owner, group_id, token, .. } = setup(&env);
+        client.set_remainder_policy(&group_id, &RemainderPolicy::RoundRobin, &owner);
+
+        let first = client.preview_split(&group_id, &symbol_short!("alice"), &100, &equal(&env));
+        client.add_transaction(&symbol_short!("alice"), &group_id, &100, &token, &Vec::new(&env), &equal(&env));
+        let second = client.preview_split(&group_id, &symbol_short!("alice"), &100, &equal(&env));
+
+        // tx 1 starts at member index 1, tx 2 at member index 2
+        assert_eq!(first.get(symbol_short!("bob")), Some(34));
+        assert_eq!(second.get(symbol_short!("carol")), Some(34));
+        assert_eq!(client.get_balance(&group_id, &symbol_short!("bob"), &token), -34);
+    }
+
+    #[test]
+    fn test_largest_remainder_follows_weights() {
+        let env = Env::default();
+        let Setup { client, owner, group_id, .. } = setup(&env);
+        client.set_remainder_policy(&group_id, &RemainderPolicy::LargestRemainder, &owner);
+
+        let mut weights = Map::new(&env);
+        weights.set(symbol_short!("alice"), 1);
+        weights.set(symbol_short!("bob"), 2);
+        weights.set(symbol_short!("carol"), 4);
+        let shares = client.preview_split(&group_id, &symbol_short!("alice"), &100, &Split::Shares(weights));
+
+        // 14.28, 28.57 and 57.14: the single leftover stroop goes to bob
+        assert_eq!(shares.get(symbol_short!("alice")), Some(14));
+        assert_eq!(shares.get(symbol_short!("bob")), Some(29));
+        assert_eq!(shares.get(symbol_short!("carol")), Some(57));
+    }
+
+    #[test]
+    #[should_panic(expected = "Error(Contract, #8)")]
+    fn test_exact_split_must_cover_amount() {
+        let env = Env::default();
+        let Setup { client, group_id, token, .. } = setup(&env);
+
+        let mut amounts = Map::new(&env);
+        amounts.set(symbol_short!("alice"), 40);
+        amounts.set(symbol_short!("bob"), 40);
+        client.add_transaction(&symbol_short!("alice"), &group_id, &100, &token, &Vec::new(&env), &Split::Exact(amounts));
+    }
+
+    #[test]
+    fn test_itemized_bill_allocates_extras_by_subtotal() {
+        let env = Env::default();
+        let Setup { client, group_id, token, .. } = setup(&env);
+
+        // pizza 20 each, wine 20 each for alice and bob, dessert on the house: subtotals 40/40/20
+        let items = Vec::from_array(&env, [
+            Item { label: String::from_str(&env, "pizza"), price: 30, quantity: 2, members: Vec::new(&env) },
+            Item { label: String::from_str(&env, "dessert"), price: 0, quantity: 1, members: Vec::from_array(&env, [symbol_short!("carol")]) },
+            Item {
+                label: String::from_str(&env, "wine"),
+                price: 40,
+                quantity: 1,
+                members: Vec::from_array(&env, [symbol_short!("alice"), symbol_short!("bob")]),
+            },
+        ]);
+        let bill = Bill { items, tax: 10, tip: 15, discount: 5 };
+        let tx_id = client.add_itemized_transaction(&symbol_short!("alice"), &group_id, &token, &Vec::new(&env), &bill);
+
+        let tx = client.get_transaction(&tx_id);
+        assert_eq!(tx.amount, 120);
+        assert_eq!(tx.shares.get(symbol_short!("alice")), Some(48));
+        assert_eq!(tx.shares.get(symbol_short!("bob")), Some(48));
+        assert_eq!(tx.shares.get(symbol_short!("carol")), Some(24));
+        assert_eq!(client.get_bill(&tx_id), bill);
+        assert_eq!(client.get_balance(&group_id, &symbol_short!("alice"), &token), 72);
+    }
+
+    #[test]
+    fn test_receipt_attachments_verify_against_hash() {
+        let env = Env::default();
+        let Setup { client, group_id, token, .. } = setup(&env);
+
+        let receipt = Bytes::from_slice(&env, b"dinner receipt");
+        let attachment = Attachment {
+            hash: env.crypto().sha256(&receipt).to_bytes(),
+            uri: String::from_str(&env, "ipfs://bafybeigdyrzt5sfp7udm7hu76uh7y26nf3efuylqabf3oclgtqy55fbzdi"),
+            mime: String::from_str(&env, "image/jpeg"),
+        };
+        let tx_id = client.add_transaction(&symbol_short!("alice"), &group_id, &90, &token, &Vec::from_array(&env, [attachment]), &equal(&env));
+        assert!(client.verify_receipt(&tx_id, &receipt));
+        assert!(!client.verify_receipt(&tx_id, &Bytes::from_slice(&env, b"forged receipt")));
+
+        let scan = Bytes::from_slice(&env, b"card slip");
+        client.attach(&tx_id, &Attachment {
+            hash: env.crypto().sha256(&scan).to_bytes(),
+            uri: String::from_str(&env, "https://example.com/slip.pdf"),
+            mime: String::from_str(&env, "application/pdf"),
+        });
+        assert_eq!(client.get_transaction(&tx_id).attachments.len(), 2);
+        assert!(client.verify_receipt(&tx_id, &scan));
+
+        let empty = Attachment { hash: BytesN::from_array(&env, &[0; 32]), uri: String::from_str(&env, ""), mime: String::from_str(&env, "") };
+        assert_eq!(client.try_attach(&tx_id, &empty), Err(Ok(Error::InvalidAttachment.into())));
+    }
+
+    #[test]
+    fn test_settle_all_zeroes_positions() {
+        let env = Env::default();
+        let Setup { client, group_id, addresses, token, .. } = setup(&env);
+
+        client.add_transaction(&symbol_short!("alice"), &group_id, &90, &token, &Vec::new(&env), &equal(&env));
+        client.add_transaction(&symbol_short!("bob"), &group_id, &30, &token, &Vec::new(&env), &equal(&env));
+
+        // alice +50, bob -10, carol -40
+        let plan = client.suggest_settlements(&group_id, &token);
+        assert_eq!(plan.len(), 2);
+        assert_eq!(plan.get_unchecked(0), Settlement { from: symbol_short!("carol"), to: symbol_short!("alice"), amount: 40 });
+        assert_eq!(plan.get_unchecked(1), Settlement { from: symbol_short!("bob"), to: symbol_short!("alice"), amount: 10 });
+
+        client.settle_all(&group_id, &token);
+        let balances = TokenClient::new(&env, &token);
+        assert_eq!(balances.balance(&addresses.get_unchecked(0)), 1_050);
+        assert_eq!(balances.balance(&addresses.get_unchecked(2)), 960);
+        assert_eq!(client.get_balance(&group_id, &symbol_short!("alice"), &token), 0);
+        assert!(client.suggest_settlements(&group_id, &token).is_empty());
+    }
+
+    #[test]
+    fn test_quorum_defers_balances_until_approved() {
+        let env = Env::default();
+        let Setup { client, owner, group_id, token, .. } = setup(&env);
+        client.set_quorum(&group_id, &Quorum::Percent(60), &0, &owner);
+
+        let tx_id = client.add_transaction(&symbol_short!("alice"), &group_id, &90, &token, &Vec::new(&env), &equal(&env));
+        assert_eq!(client.get_transaction(&tx_id).status, TxStatus::Pending);
+        assert_eq!(client.get_balance(&group_id, &symbol_short!("alice"), &token), 0);
+
+        assert_eq!(client.approve_transaction(&tx_id, &symbol_short!("bob")), TxStatus::Approved);
+        assert_eq!(client.get_balance(&group_id, &symbol_short!("alice"), &token), 60);
+        assert_eq!(client.get_balance(&group_id, &symbol_short!("carol"), &token), -30);
+    }
+
+    #[test]
+    fn test_events_carry_group_and_actor() {
+        let env = Env::default();
+        let Setup { client, owner, group_id, addresses, token } = setup(&env);
+        client.set_quorum(&group_id, &Quorum::Count(2), &0, &owner);
+
+        let tx_id = client.add_transaction(&symbol_short!("alice"), &group_id, &90, &token, &Vec::new(&env), &equal(&env));
+        let (_, topics, data) = env.events().all().last_unchecked();
+        assert_eq!(topics, (Symbol::new(&env, "expense_added"), group_id, addresses.get_unchecked(0)).into_val(&env));
+        assert_eq!(<(u64, u64, Address)>::from_val(&env, &data), (tx_id, 90, token.clone()));
+
+        client.approve_transaction(&tx_id, &symbol_short!("bob"));
+        let (_, topics, data) = env.events().all().last_unchecked();
+        assert_eq!(topics, (Symbol::new(&env, "expense_approved"), group_id, addresses.get_unchecked(1)).into_val(&env));
+        assert_eq!(u64::from_val(&env, &data), tx_id);
+
+        client.set_remainder_policy(&group_id, &RemainderPolicy::RoundRobin, &owner);
+        let (_, topics, data) = env.events().all().last_unchecked();
+        assert_eq!(topics, (Symbol::new(&env, "remainder_policy_set"), group_id, owner.clone()).into_val(&env));
+        assert_eq!(RemainderPolicy::from_val(&env, &data), RemainderPolicy::RoundRobin);
+
+        let dave = Address::generate(&env);
+        client.create_member(&symbol_short!("dave"), &dave);
+        let (_, topics, _) = env.events().all().last_unchecked();
+        assert_eq!(topics, (Symbol::new(&env, "member_created"), NO_GROUP, dave).into_val(&env));
+    }
+
+    #[test]
+    fn test_bump_group_extends_related_entries() {
+        let env = Env::default();
+        // Keeps the contract instance and the token alive across the jump
+        env.ledger().with_mut(|ledger| ledger.min_persistent_entry_ttl = BUMP_AMOUNT);
+        let Setup { client, group_id, token, .. } = setup(&env);
+        let tx_id = client.add_transaction(&symbol_short!("alice"), &group_id, &90, &token, &Vec::new(&env), &equal(&env));
+        let loan_id = client.create_loan(&group_id, &symbol_short!("alice"), &symbol_short!("bob"), &token, &100, &0, &SECONDS_PER_YEAR);
+        let recurring_id = client.add_recurring(
+            &symbol_short!("bob"), &group_id, &30, &token, &Vec::new(&env), &equal(&env), &Interval::Ledgers(DAY_IN_LEDGERS), &1, &0,
+        );
+        assert_eq!(client.group_ttl(&group_id), BUMP_AMOUNT);
+
+        env.ledger().with_mut(|ledger| ledger.sequence_number += 10 * DAY_IN_LEDGERS);
+        assert_eq!(client.group_ttl(&group_id), 20 * DAY_IN_LEDGERS);
+
+        client.bump_group(&group_id);
+        client.bump_transactions(&group_id, &0, &MAX_PAGE_SIZE);
+        assert_eq!(client.group_ttl(&group_id), BUMP_AMOUNT);
+        env.as_contract(&client.address, || {
+            let position = DataKey::Position(group_id, symbol_short!("carol"), token.clone());
+            assert_eq!(env.storage().persistent().get_ttl(&position), BUMP_AMOUNT);
+            assert_eq!(env.storage().persistent().get_ttl(&DataKey::Member(symbol_short!("carol"))), BUMP_AMOUNT);
+            assert_eq!(env.storage().persistent().get_ttl(&DataKey::Transaction(tx_id)), BUMP_AMOUNT);
+            assert_eq!(env.storage().persistent().get_ttl(&DataKey::Loan(loan_id)), BUMP_AMOUNT);
+            assert_eq!(env.storage().persistent().get_ttl(&DataKey::Recurring(recurring_id)), BUMP_AMOUNT);
+        });
+    }
+
+    #[test]
+    fn test_edit_cancel_and_reverse_expenses() {
+        let env = Env::default();
+        let Setup { client, owner, group_id, token, .. } = setup(&env);
+        client.set_quorum(&group_id, &Quorum::Count(2), &0, &owner);
+
+        // A typo is fixed before anyone approves, the old votes are dropped
+        let tx_id = client.add_transaction(&symbol_short!("alice"), &group_id, &900, &token, &Vec::new(&env), &equal(&env));
+        client.edit_transaction(&tx_id, &90, &token, &equal(&env));
+        assert_eq!(client.get_transaction(&tx_id).amount, 90);
+        client.approve_transaction(&tx_id, &symbol_short!("bob"));
+        assert_eq!(client.get_balance(&group_id, &symbol_short!("alice"), &token), 60);
+        assert_eq!(client.try_edit_transaction(&tx_id, &30, &token, &equal(&env)), Err(Ok(Error::TransactionNotPending.into())));
+
+        // Editing would make the author the only payer of a shared bill
+        let mut payers = Map::new(&env);
+        payers.set(symbol_short!("alice"), 60);
+        payers.set(symbol_short!("bob"), 30);
+        let shared = client.add_multi_payer_transaction(&symbol_short!("carol"), &group_id, &payers, &token, &Vec::new(&env), &equal(&env));
+        assert_eq!(client.try_edit_transaction(&shared, &90, &token, &equal(&env)), Err(Ok(Error::MultiplePayers.into())));
+        client.cancel_transaction(&shared);
+
+        let cancelled = client.add_transaction(&symbol_short!("bob"), &group_id, &30, &token, &Vec::new(&env), &equal(&env));
+        client.cancel_transaction(&cancelled);
+        assert_eq!(client.get_transaction(&cancelled).status, TxStatus::Cancelled);
+
+        // The reversal is approved like any expense and leaves the original in the history
+        let reversal_id = client.reverse_transaction(&tx_id);
+        assert_eq!(client.try_reverse_transaction(&tx_id), Err(Ok(Error::InvalidReversal.into())));
+        assert_eq!(client.approve_transaction(&reversal_id, &symbol_short!("carol")), TxStatus::Approved);
+        assert_eq!(client.get_transaction(&reversal_id).reverses, tx_id);
+        assert_eq!(client.get_transaction(&tx_id).status, TxStatus::Reversed);
+        assert_eq!(client.get_balance(&group_id, &symbol_short!("alice"), &token), 0);
+        assert_eq!(client.get_balance(&group_id, &symbol_short!("carol"), &token), 0);
+        assert_eq!(client.transaction_count(&group_id), 4);
+    }
+
+    #[test]
+    fn test_close_group_settles_and_keeps_summary() {
+        let env = Env::default();
+        let Setup { client, owner, group_id, addresses, token } = setup(&env);
+        client.add_transaction(&symbol_short!("alice"), &group_id, &90, &token, &Vec::new(&env), &equal(&env));
+        client.deposit(&group_id, &symbol_short!("bob"), &token, &50);
+
+        assert_eq!(client.try_close_group(&group_id, &false, &owner), Err(Ok(Error::OutstandingBalance.into())));
+        client.close_group(&group_id, &true, &owner);
+
+        let balances = TokenClient::new(&env, &token);
+        assert_eq!(balances.balance(&addresses.get_unchecked(0)), 1_060);
+        assert_eq!(balances.balance(&addresses.get_unchecked(1)), 970);
+        let summary = client.get_summary(&group_id);
+        assert_eq!(summary.transaction_count, 1);
+        assert_eq!(summary.totals.get(token.clone()), Some(90));
+        assert!(client.groups_of(&addresses.get_unchecked(0), &0, &10).is_empty());
+        assert_eq!(
+            client.try_add_transaction(&symbol_short!("alice"), &group_id, &30, &token, &Vec::new(&env), &equal(&env)),
+            Err(Ok(Error::GroupClosed.into())),
+        );
+    }
+
+    #[test]
+    fn test_multi_payer_expense_credits_each_payer() {
+        let env = Env::default();
+        let Setup { client, group_id, token, .. } = setup(&env);
+
+        let mut payers = Map::new(&env);
+        payers.set(symbol_short!("alice"), 60);
+        payers.set(symbol_short!("bob"), 30);
+        let tx_id = client.add_multi_payer_transaction(&symbol_short!("carol"), &group_id, &payers, &token, &Vec::new(&env), &equal(&env));
+
+        assert_eq!(client.get_transaction(&tx_id).amount, 90);
+        assert_eq!(client.get_balance(&group_id, &symbol_short!("alice"), &token), 30);
+        assert_eq!(client.get_balance(&group_id, &symbol_short!("bob"), &token), 0);
+        assert_eq!(client.get_balance(&group_id, &symbol_short!("carol"), &token), -30);
+    }
+
+    #[test]
+    fn test_guest_is_claimed_by_an_address() {
+        let env = Env::default();
+        let Setup { client, owner, group_id, token, .. } = setup(&env);
+        client.add_guest(&group_id, &symbol_short!("dave"), &String::from_str(&env, "Dave"), &owner);
+
+        client.add_transaction(&symbol_short!("alice"), &group_id, &100, &token, &Vec::new(&env), &equal(&env));
+        assert_eq!(client.get_balance(&group_id, &symbol_short!("dave"), &token), -25);
+        assert_eq!(client.try_create_member(&symbol_short!("dave"), &Address::generate(&env)), Err(Ok(Error::UserIdAlreadyExists.into())));
+
+        let dave = Address::generate(&env);
+        client.claim_guest(&symbol_short!("dave"), &dave);
+        assert_eq!(
+            client.try_approve_claim(&symbol_short!("dave"), &Address::generate(&env), &owner),
+            Err(Ok(Error::ClaimNotFound.into())),
+        );
+        client.approve_claim(&symbol_short!("dave"), &dave, &owner);
+
+        assert_eq!(client.get_balance(&group_id, &symbol_short!("dave"), &token), -25);
+        assert_eq!(client.get_role(&group_id, &symbol_short!("dave")), Role::Member);
+        assert_eq!(client.groups_of(&dave, &0, &10), Vec::from_array(&env, [group_id]));
+        assert!(client.try_get_guest(&symbol_short!("dave")).is_err());
+    }
+
+    #[test]
+    fn test_guest_positions_are_reassigned_before_closing() {
+        let env = Env::default();
+        let Setup { client, owner, group_id, token, .. } = setup(&env);
+        client.add_guest(&group_id, &symbol_short!("dave"), &String::from_str(&env, "Dave"), &owner);
+        client.add_transaction(&symbol_short!("alice"), &group_id, &100, &token, &Vec::new(&env), &equal(&env));
+
+        // dave owes 25 and cannot pay, the members still settle between themselves
+        let settled = client.settle_all(&group_id, &token);
+        assert!(settled.iter().all(|settlement| settlement.from != symbol_short!("dave")));
+        assert_eq!(client.get_balance(&group_id, &symbol_short!("dave"), &token), -25);
+        assert_eq!(client.try_close_group(&group_id, &true, &owner), Err(Ok(Error::GuestCannotSettle.into())));
+        assert_eq!(
+            client.try_remove_member(&group_id, &symbol_short!("dave"), &Some(Resolution::Settle), &owner),
+            Err(Ok(Error::GuestCannotSettle.into())),
+        );
+
+        client.remove_member(&group_id, &symbol_short!("dave"), &Some(Resolution::Reassign(symbol_short!("bob"))), &owner);
+        client.close_group(&group_id, &true, &owner);
+        assert_eq!(client.get_summary(&group_id).totals.get(token), Some(100));
+    }
+
+    #[test]
+    fn test_registry_handles_become_user_ids() {
+        let env = Env::default();
+        let Setup { client, owner, group_id, .. } = setup(&env);
+        let registry = MockRegistryClient::new(&env, &env.register_contract(None, MockRegistry));
+        let admin = Address::generate(&env);
+        assert_eq!(client.try_set_registry(&registry.address), Err(Ok(Error::Unauthorized.into())));
+        client.init(&admin);
+        assert_eq!(client.try_init(&owner), Err(Ok(Error::AlreadyInitialized.into())));
+        client.set_registry(&registry.address);
+        assert_eq!(env.auths().first().map(|(address, _)| address.clone()), Some(admin));
+
+        let dave = Address::generate(&env);
+        registry.register(&symbol_short!("dave"), &dave);
+        assert_eq!(client.try_create_member(&symbol_short!("dave"), &Address::generate(&env)), Err(Ok(Error::HandleNotOwned.into())));
+        assert_eq!(client.try_create_member(&symbol_short!("erin"), &dave), Err(Ok(Error::HandleNotOwned.into())));
+        client.create_member(&symbol_short!("dave"), &dave);
+        client.add_member_to_group(&group_id, &symbol_short!("dave"), &owner);
+
+        let name = String::from_str(&env, "Frank");
+        registry.register(&symbol_short!("frank"), &Address::generate(&env));
+        assert_eq!(client.try_add_guest(&group_id, &symbol_short!("frank"), &name, &owner), Err(Ok(Error::HandleNotOwned.into())));
+        client.add_guest(&group_id, &symbol_short!("frank_g"), &name, &owner);
+    }
+
+    #[test]
+    fn test_bump_group_keeps_the_registry_alive() {
+        let env = Env::default();
+        env.ledger().with_mut(|ledger| ledger.min_persistent_entry_ttl = BUMP_AMOUNT);
+        let Setup { client, group_id, .. } = setup(&env);
+        let registry = MockRegistryClient::new(&env, &env.register_contract(None, MockRegistry));
+        client.init(&Address::generate(&env));
+        client.set_registry(&registry.address);
+
+        env.ledger().with_mut(|ledger| ledger.sequence_number += 10 * DAY_IN_LEDGERS);
+        client.bump_group(&group_id);
+        env.as_contract(&client.address, || {
+            assert_eq!(env.storage().instance().get_ttl(), BUMP_AMOUNT);
+            assert_eq!(env.storage().instance().get::<DataKey, Address>(&DataKey::Registry), Some(registry.address.clone()));
+        });
+        assert_eq!(client.try_create_member(&symbol_short!("dave"), &Address::generate(&env)), Err(Ok(Error::HandleNotOwned.into())));
+    }
+
+    #[test]
+    fn test_one_address_holds_one_member_id_per_group() {
+        let env = Env::default();
+        let Setup { client, owner, group_id, addresses, .. } = setup(&env);
+        let alice = addresses.get_unchecked(0);
+
+        client.create_member(&symbol_short!("alice2"), &alice);
+        assert_eq!(
+            client.try_add_member_to_group(&group_id, &symbol_short!("alice2"), &owner),
+            Err(Ok(Error::MemberAlreadyExists.into())),
+        );
+
+        client.add_guest(&group_id, &symbol_short!("dave"), &String::from_str(&env, "Dave"), &owner);
+        client.claim_guest(&symbol_short!("dave"), &alice);
+        assert_eq!(
+            client.try_approve_claim(&symbol_short!("dave"), &alice, &owner),
+            Err(Ok(Error::MemberAlreadyExists.into())),
+        );
+        assert_eq!(client.get_role(&group_id, &symbol_short!("alice")), Role::Member);
+    }
+
+    #[test]
+    fn test_rejections_close_out_unreachable_quorum() {
+        let env = Env::default();
+        let Setup { client, owner, group_id, token, .. } = setup(&env);
+        client.set_quorum(&group_id, &Quorum::Count(3), &0, &owner);
+
+        let tx_id = client.add_transaction(&symbol_short!("alice"), &group_id, &90, &token, &Vec::new(&env), &equal(&env));
+        assert_eq!(client.reject_transaction(&tx_id, &symbol_short!("carol")), TxStatus::Rejected);
+        assert_eq!(client.get_balance(&group_id, &symbol_short!("alice"), &token), 0);
+    }
+
+    #[test]
+    fn test_pending_expense_expires() {
+        let env = Env::default();
+        let Setup { client, owner, group_id, token, .. } = setup(&env);
+        client.set_quorum(&group_id, &Quorum::Count(2), &10, &owner);
+
+        let tx_id = client.add_transaction(&symbol_short!("alice"), &group_id, &90, &token, &Vec::new(&env), &equal(&env));
+        assert!(client.try_expire_transaction(&tx_id).is_err());
+
+        env.ledger().with_mut(|ledger| ledger.sequence_number += 11);
+        assert_eq!(client.approve_transaction(&tx_id, &symbol_short!("bob")), TxStatus::Expired);
+        assert_eq!(client.get_balance(&group_id, &symbol_short!("bob"), &token), 0);
+    }
+
+    #[test]
+    fn test_leave_requires_zero_or_resolved_balance() {
+        let env = Env::default();
+        let Setup { client, group_id, token, .. } = setup(&env);
+        client.add_transaction(&symbol_short!("alice"), &group_id, &90, &token, &Vec::new(&env), &equal(&env));
+
+        let result = client.try_leave_group(&group_id, &symbol_short!("bob"), &None);
+        assert_eq!(result, Err(Ok(Error::OutstandingBalance.into())));
+
+        client.leave_group(&group_id, &symbol_short!("bob"), &Some(Resolution::Reassign(symbol_short!("carol"))));
+        assert_eq!(client.get_balance(&group_id, &symbol_short!("carol"), &token), -60);
+        assert_eq!(client.suggest_settlements(&group_id, &token).len(), 1);
+    }
+
+    #[test]
+    fn test_remove_member_settles_through_token() {
+        let env = Env::default();
+        let Setup { client, owner, group_id, addresses, token } = setup(&env);
+        client.add_transaction(&symbol_short!("alice"), &group_id, &90, &token, &Vec::new(&env), &equal(&env));
+
+        client.remove_member(&group_id, &symbol_short!("alice"), &Some(Resolution::Settle), &owner);
+
+        assert_eq!(TokenClient::new(&env, &token).balance(&addresses.get_unchecked(0)), 1_060);
+        assert_eq!(client.get_balance(&group_id, &symbol_short!("bob"), &token), 0);
+        assert_eq!(client.get_balance(&group_id, &symbol_short!("alice"), &token), 0);
+        assert!(client.suggest_settlements(&group_id, &token).is_empty());
+    }
+
+    #[test]
+    fn test_roles_gate_group_actions() {
+        let env = Env::default();
+        let Setup { client, owner, group_id, addresses, token } = setup(&env);
+        let alice = addresses.get_unchecked(0);
+
+        assert!(!client.has_permission(&group_id, &alice, &Action::ConfigureSplit));
+        assert!(client.try_set_quorum(&group_id, &Quorum::Count(2), &0, &alice).is_err());
+
+        client.set_role(&group_id, &symbol_short!("alice"), &Role::Admin, &owner);
+        client.set_quorum(&group_id, &Quorum::Count(2), &0, &alice);
+        assert!(!client.has_permission(&group_id, &alice, &Action::CloseGroup));
+
+        client.set_role(&group_id, &symbol_short!("carol"), &Role::Viewer, &owner);
+        let tx_id = client.add_transaction(&symbol_short!("alice"), &group_id, &90, &token, &Vec::new(&env), &equal(&env));
+        assert!(client.try_approve_transaction(&tx_id, &symbol_short!("carol")).is_err());
+        assert!(client.try_add_transaction(&symbol_short!("carol"), &group_id, &90, &token, &Vec::new(&env), &equal(&env)).is_err());
+    }
+
+    #[test]
+    fn test_ownership_transfer_needs_acceptance() {
+        let env = Env::default();
+        let Setup { client, owner, group_id, addresses, .. } = setup(&env);
+        let bob = addresses.get_unchecked(1);
+
+        client.transfer_ownership(&group_id, &bob, &owner);
+        assert_eq!(client.get_role(&group_id, &symbol_short!("bob")), Role::Member);
+        assert!(client.try_accept_ownership(&group_id, &addresses.get_unchecked(2)).is_err());
+
+        client.accept_ownership(&group_id, &bob);
+        assert_eq!(client.get_role(&group_id, &symbol_short!("bob")), Role::Owner);
+        assert!(!client.has_permission(&group_id, &owner, &Action::ManageMembers));
+    }
+
+    #[test]
+    fn test_invitation_needs_invitee_acceptance() {
+        let env = Env::default();
+        let Setup { client, owner, group_id, .. } = setup(&env);
+        let dave = Address::generate(&env);
+        client.create_member(&symbol_short!("dave"), &dave);
+
+        assert!(client.try_accept_invite(&group_id, &symbol_short!("dave")).is_err());
+
+        client.invite(&group_id, &dave, &owner);
+        client.decline_invite(&group_id, &dave);
+        assert!(!client.has_invite(&group_id, &dave));
+
+        client.invite(&group_id, &dave, &owner);
+        client.accept_invite(&group_id, &symbol_short!("dave"));
+        assert_eq!(client.get_role(&group_id, &symbol_short!("dave")), Role::Member);
+    }
+
+    #[test]
+    fn test_join_with_invite_code() {
+        let env = Env::default();
+        let Setup { client, owner, group_id, .. } = setup(&env);
+        client.create_member(&symbol_short!("dave"), &Address::generate(&env));
+
+        let secret = Bytes::from_slice(&env, b"pizza-night");
+        client.set_invite_code(&group_id, &env.crypto().sha256(&secret).to_bytes(), &owner);
+
+        let wrong = Bytes::from_slice(&env, b"pasta-night");
+        assert_eq!(client.try_join_with_code(&group_id, &symbol_short!("dave"), &wrong), Err(Ok(Error::InvalidInviteCode.into())));
+
+        client.join_with_code(&group_id, &symbol_short!("dave"), &secret);
+        assert_eq!(client.get_role(&group_id, &symbol_short!("dave")), Role::Member);
+    }
+
+    #[test]
+    fn test_paginated_views() {
+        let env = Env::default();
+        let Setup { client, owner, group_id, token, .. } = setup(&env);
+        for _ in 0..4 {
+            client.create_group(&owner);
+        }
+        for _ in 0..55 {
+            client.add_transaction(&symbol_short!("alice"), &group_id, &3, &token, &Vec::new(&env), &equal(&env));
+        }
+
+        assert_eq!(client.group_count(), 5);
+        let groups = client.list_groups(&3, &10);
+        assert_eq!(groups.len(), 2);
+        assert_eq!(groups.get_unchecked(0).group_id, 4);
+
+        assert_eq!(client.transaction_count(&group_id), 55);
+        let page = client.list_transactions(&group_id, &45, &100);
+        assert_eq!(page.len(), 10);
+        assert_eq!(page.get_unchecked(0).tx_id, 46);
+        assert_eq!(page.get_unchecked(9).tx_id, 55);
+    }
+
+    #[test]
+    fn test_membership_index_follows_join_and_leave() {
+        let env = Env::default();
+        let Setup { client, owner, group_id: first, addresses, .. } = setup(&env);
+        let alice = addresses.get_unchecked(0);
+        let second = client.create_group(&owner);
+        let third = client.create_group(&owner);
+        client.add_member_to_group(&second, &symbol_short!("alice"), &owner);
+        client.add_member_to_group(&third, &symbol_short!("alice"), &owner);
+
+        assert_eq!(client.groups_of(&alice, &0, &10), Vec::from_array(&env, [first, second, third]));
+
+        client.leave_group(&first, &symbol_short!("alice"), &None);
+        assert_eq!(client.groups_of(&alice, &0, &10), Vec::from_array(&env, [third, second]));
+        assert_eq!(client.groups_of(&alice, &1, &10), Vec::from_array(&env, [second]));
+    }
+
+    #[test]
+    fn test_positions_and_settlement_per_asset() {
+        let env = Env::default();
+        let Setup { client, owner, group_id, addresses, token } = setup(&env);
+        let usdc = env.register_stellar_asset_contract_v2(Address::generate(&env)).address();
+        for address in addresses.iter() {
+            StellarAssetClient::new(&env, &usdc).mint(&address, &100);
+        }
+
+        assert_eq!(
+            client.try_add_transaction(&symbol_short!("bob"), &group_id, &60, &usdc, &Vec::new(&env), &equal(&env)),
+            Err(Ok(Error::AssetNotAccepted.into()))
+        );
+        client.add_asset(&group_id, &usdc, &owner);
+
+        client.add_transaction(&symbol_short!("alice"), &group_id, &90, &token, &Vec::new(&env), &equal(&env));
+        client.add_transaction(&symbol_short!("bob"), &group_id, &60, &usdc, &Vec::new(&env), &equal(&env));
+        assert_eq!(client.get_balance(&group_id, &symbol_short!("alice"), &token), 60);
+        assert_eq!(client.get_balance(&group_id, &symbol_short!("alice"), &usdc), -20);
+
+        client.settle_all(&group_id, &usdc);
+        assert_eq!(TokenClient::new(&env, &usdc).balance(&addresses.get_unchecked(1)), 140);
+        assert!(client.suggest_settlements(&group_id, &usdc).is_empty());
+        assert_eq!(client.suggest_settlements(&group_id, &token).len(), 2);
+    }
+
+    #[test]
+    fn test_oracle_converts_into_base_asset() {
+        let env = Env::default();
+        let Setup { client, owner, group_id, token, .. } = setup(&env);
+        let eurc = env.register_stellar_asset_contract_v2(Address::generate(&env)).address();
+        client.add_asset(&group_id, &eurc, &owner);
+
+        let oracle = MockOracleClient::new(&env, &env.register_contract(None, MockOracle));
+        env.ledger().with_mut(|ledger| ledger.timestamp = 1_000);
+        oracle.set_price(&token, &100_000_000_000_000, &990);
+        oracle.set_price(&eurc, &110_000_000_000_000, &950);
+        client.set_oracle(&group_id, &oracle.address, &token, &60, &owner);
+
+        let tx_id = client.add_transaction(&symbol_short!("alice"), &group_id, &300, &eurc, &Vec::new(&env), &equal(&env));
+        let transaction = client.get_transaction(&tx_id);
+        assert_eq!((transaction.amount, transaction.asset), (330, token.clone()));
+        assert_eq!(client.get_balance(&group_id, &symbol_short!("alice"), &token), 220);
+        let conversion = client.get_conversion(&tx_id).unwrap();
+        assert_eq!((conversion.amount, conversion.rate, conversion.timestamp), (300, 110_000_000_000_000, 950));
+
+        env.ledger().with_mut(|ledger| ledger.timestamp = 1_011);
+        assert_eq!(
+            client.try_add_transaction(&symbol_short!("alice"), &group_id, &300, &eurc, &Vec::new(&env), &equal(&env)),
+            Err(Ok(Error::StalePrice.into()))
+        );
+    }
+
+    #[test]
+    fn test_converted_multi_payer_expense_keeps_its_payers() {
+        let env = Env::default();
+        let Setup { client, owner, group_id, token, .. } = setup(&env);
+        let eurc = env.register_stellar_asset_contract_v2(Address::generate(&env)).address();
+        client.add_asset(&group_id, &eurc, &owner);
+
+        let oracle = MockOracleClient::new(&env, &env.register_contract(None, MockOracle));
+        oracle.set_price(&token, &100_000_000_000_000, &0);
+        oracle.set_price(&eurc, &110_000_000_000_000, &0);
+        client.set_oracle(&group_id, &oracle.address, &token, &60, &owner);
+
+        // 91 at 1.1 is 100, the stroop lost rescaling 67.03 and 32.97 goes to alice
+        let mut payers = Map::new(&env);
+        payers.set(symbol_short!("alice"), 61);
+        payers.set(symbol_short!("bob"), 30);
+        let tx_id = client.add_multi_payer_transaction(&symbol_short!("carol"), &group_id, &payers, &eurc, &Vec::new(&env), &equal(&env));
+
+        let transaction = client.get_transaction(&tx_id);
+        assert_eq!(transaction.amount, 100);
+        assert_eq!(transaction.payers.get(symbol_short!("alice")), Some(68));
+        assert_eq!(transaction.payers.get(symbol_short!("bob")), Some(32));
+        assert_eq!(transaction.payers.len(), 2);
+    }
+
+    #[test]
+    fn test_conversion_follows_token_decimals() {
+        let env = Env::default();
+        let Setup { client, owner, group_id, token, .. } = setup(&env);
+        let wrapped = env.register_contract(None, mock_token::MockToken);
+        client.add_asset(&group_id, &wrapped, &owner);
+
+        let oracle = MockOracleClient::new(&env, &env.register_contract(None, MockOracle));
+        oracle.set_price(&token, &100_000_000_000_000, &0);
+        oracle.set_price(&wrapped, &100_000_000_000_000, &0);
+        client.set_oracle(&group_id, &oracle.address, &token, &60, &owner);
+
+        // Same price, 9 decimals against the 7 of the base asset
+        let tx_id = client.add_transaction(&symbol_short!("alice"), &group_id, &3_000_000_000, &wrapped, &Vec::new(&env), &equal(&env));
+        assert_eq!(client.get_transaction(&tx_id).amount, 30_000_000);
+        assert_eq!(client.get_conversion(&tx_id).unwrap().decimals, 16);
+    }
+
+    #[test]
+    fn test_treasury_pays_merchant_and_refunds_pro_rata() {
+        let env = Env::default();
+        let Setup { client, owner, group_id, addresses, token } = setup(&env);
+        let merchant = Address::generate(&env);
+        client.set_quorum(&group_id, &Quorum::Count(2), &0, &owner);
+
+        client.deposit(&group_id, &symbol_short!("alice"), &token, &300);
+        client.deposit(&group_id, &symbol_short!("bob"), &token, &100);
+        assert_eq!(client.treasury_balance(&group_id, &token), 400);
+
+        let withdrawal_id = client.propose_withdrawal(&group_id, &symbol_short!("alice"), &token, &merchant, &150);
+        assert_eq!(client.get_withdrawal(&withdrawal_id).status, TxStatus::Pending);
+        assert_eq!(client.approve_withdrawal(&withdrawal_id, &symbol_short!("carol")), TxStatus::Approved);
+        assert_eq!(TokenClient::new(&env, &token).balance(&merchant), 150);
+
+        assert_eq!(
+            client.try_propose_withdrawal(&group_id, &symbol_short!("bob"), &token, &merchant, &300),
+            Err(Ok(Error::InsufficientTreasury.into()))
+        );
+
+        // 250 left over, split 3:1
+        client.close_treasury(&group_id, &owner);
+        let balances = TokenClient::new(&env, &token);
+        assert_eq!(balances.balance(&addresses.get_unchecked(0)), 700 + 188);
+        assert_eq!(balances.balance(&addresses.get_unchecked(1)), 900 + 62);
+        assert_eq!(client.treasury_balance(&group_id, &token), 0);
+    }
+
+    #[test]
+    fn test_withdrawal_needs_another_member_under_default_quorum() {
+        let env = Env::default();
+        let Setup { client, group_id, addresses, token, .. } = setup(&env);
+        let alice = addresses.get_unchecked(0);
+        client.deposit(&group_id, &symbol_short!("bob"), &token, &500);
+        client.deposit(&group_id, &symbol_short!("carol"), &token, &500);
+
+        let withdrawal_id = client.propose_withdrawal(&group_id, &symbol_short!("alice"), &token, &alice, &1_000);
+        assert_eq!(client.get_withdrawal(&withdrawal_id).status, TxStatus::Pending);
+        assert_eq!(TokenClient::new(&env, &token).balance(&alice), 1_000);
+        assert_eq!(client.treasury_balance(&group_id, &token), 1_000);
+
+        assert_eq!(client.reject_withdrawal(&withdrawal_id, &symbol_short!("bob")), TxStatus::Pending);
+        assert_eq!(client.approve_withdrawal(&withdrawal_id, &symbol_short!("carol")), TxStatus::Approved);
+        assert_eq!(TokenClient::new(&env, &token).balance(&alice), 2_000);
+
+        let usdc = env.register_stellar_asset_contract_v2(Address::generate(&env)).address();
+        assert_eq!(
+            client.try_propose_withdrawal(&group_id, &symbol_short!("alice"), &usdc, &alice, &1),
+            Err(Ok(Error::AssetNotAccepted.into())),
+        );
+    }
+
+    #[test]
+    fn test_second_member_id_cannot_approve_a_withdrawal() {
+        let env = Env::default();
+        let Setup { client, owner, group_id, addresses, token } = setup(&env);
+        let alice = addresses.get_unchecked(0);
+        client.set_quorum(&group_id, &Quorum::Count(2), &0, &owner);
+        client.deposit(&group_id, &symbol_short!("bob"), &token, &500);
+
+        // alice maps a second id to her own address, it cannot join next to the first
+        client.create_member(&symbol_short!("alice2"), &alice);
+        assert!(client.try_add_member_to_group(&group_id, &symbol_short!("alice2"), &owner).is_err());
+
+        let withdrawal_id = client.propose_withdrawal(&group_id, &symbol_short!("alice"), &token, &alice, &500);
+        assert_eq!(
+            client.try_approve_withdrawal(&withdrawal_id, &symbol_short!("alice2")),
+            Err(Ok(Error::Unauthorized.into())),
+        );
+        assert_eq!(client.get_withdrawal(&withdrawal_id).status, TxStatus::Pending);
+        assert_eq!(client.treasury_balance(&group_id, &token), 500);
+        assert_eq!(TokenClient::new(&env, &token).balance(&alice), 1_000);
+    }
+
+    #[test]
+    fn test_recurring_expense_schedule() {
+        let env = Env::default();
+        let Setup { client, owner, group_id, token, .. } = setup(&env);
+        env.ledger().with_mut(|ledger| ledger.timestamp = 1_000);
+
+        let month = 30 * 24 * 60 * 60;
+        let recurring_id = client.add_recurring(
+            &symbol_short!("alice"), &group_id, &900, &token, &Vec::new(&env), &equal(&env),
+            &Interval::Seconds(month), &1_000, &(1_000 + month),
+        );
+
+        client.trigger(&recurring_id);
+        assert_eq!(client.get_balance(&group_id, &symbol_short!("alice"), &token), 600);
+        assert_eq!(client.try_trigger(&recurring_id), Err(Ok(Error::NotDue.into())));
+
+        client.pause_recurring(&recurring_id, &owner);
+        env.ledger().with_mut(|ledger| ledger.timestamp += month);
+        assert!(client.try_trigger(&recurring_id).is_err());
+
+        client.resume_recurring(&recurring_id, &owner);
+        client.trigger(&recurring_id);
+        assert_eq!(client.get_balance(&group_id, &symbol_short!("alice"), &token), 1_200);
+        assert_eq!(client.get_recurring(&recurring_id).status, RecurringStatus::Finished);
+    }
+
+    #[test]
+    fn test_leaving_cancels_the_members_recurring_expenses() {
+        let env = Env::default();
+        let Setup { client, group_id, token, .. } = setup(&env);
+        let recurring_id = client.add_recurring(
+            &symbol_short!("alice"), &group_id, &90, &token, &Vec::new(&env), &equal(&env), &Interval::Ledgers(10), &0, &0,
+        );
+
+        client.leave_group(&group_id, &symbol_short!("alice"), &None);
+        assert_eq!(client.get_recurring(&recurring_id).status, RecurringStatus::Cancelled);
+        assert_eq!(client.try_trigger(&recurring_id), Err(Ok(Error::InvalidSchedule.into())));
+        env.as_contract(&client.address, || {
+            let tracked = env.storage().persistent().get::<DataKey, Vec<u64>>(&DataKey::GroupRecurring(group_id)).unwrap();
+            assert!(tracked.is_empty());
+        });
+    }
+
+    #[test]
+    fn test_loan_with_interest_and_partial_repayments() {
+        let env = Env::default();
+        let Setup { client, group_id, addresses, token, .. } = setup(&env);
+        let balances = TokenClient::new(&env, &token);
+
+        // 10% a year over half a year
+        let due = SECONDS_PER_YEAR / 2;
+        let loan_id = client.create_loan(&group_id, &symbol_short!("alice"), &symbol_short!("bob"), &token, &400, &1_000, &due);
+        assert_eq!(client.get_loan(&loan_id).total_due, 420);
+        assert_eq!(balances.balance(&addresses.get_unchecked(1)), 1_400);
+
+        assert_eq!(client.repay_loan(&loan_id, &200), LoanStatus::Open);
+        env.ledger().with_mut(|ledger| ledger.timestamp = due + 1);
+        assert_eq!(client.get_loan(&loan_id).status, LoanStatus::Overdue);
+
+        assert_eq!(client.repay_loan(&loan_id, &500), LoanStatus::Repaid);
+        assert_eq!(balances.balance(&addresses.get_unchecked(0)), 1_020);
+        assert_eq!(client.try_forgive_loan(&loan_id), Err(Ok(Error::LoanClosed.into())));
+    }
+}
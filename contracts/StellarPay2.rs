@@ -1,12 +1,29 @@
 #![no_std]
-use soroban_sdk::{contract, contracttype, contractimpl, Address, Env, Map, String, Symbol, Vec, Error, log};
+use soroban_sdk::{contract, contracttype, contractimpl, Address, Env, IntoVal, Map, String, Symbol, TryFromVal, Val, Vec, Error, log};
 
+// Every entry lives under its own persistent key
 #[contracttype]
 pub enum DataKey {
     Member(Address),
     Group(Symbol),
-    Transaction(Address), // Modified to only use Address
-    AllGroupIds, // New key for storing the list of all group IDs
+    Transaction(u64),
+    LastTransactionId,
+    GroupCount,
+    GroupIdAt(u32),                // position in creation order -> group id
+    GroupTxCount(Symbol),
+    GroupTxAt(Symbol, u32),        // transaction ids of a group, in posting order
+    UserTxCount(Address),
+    UserTxAt(Address, u32),        // transaction ids posted by a user, in posting order
+}
+
+// Instance storage layout of contracts deployed before the move to
+// persistent keys, only read by `migrate`
+#[contracttype]
+pub enum LegacyKey {
+    Member(Address),
+    Group(Symbol),
+    Transaction(Address), // one transaction per user
+    AllGroupIds,
 }
 
 #[contracttype]
@@ -24,6 +41,16 @@ pub struct Group {
 
 #[contracttype]
 pub struct Transaction {
+    tx_id: u64,
+    user_id: Address,
+    group_id: Symbol,
+    amounts: Map<Address, i128>,
+    proof: String,
+    approvals: Vec<Address>,
+}
+
+#[contracttype]
+pub struct LegacyTransaction {
     user_id: Address,
     group_id: Symbol,
     amounts: Map<Address, i128>,
@@ -31,6 +58,14 @@ pub struct Transaction {
     approvals: Vec<Address>,
 }
 
+// Entries are extended to BUMP_AMOUNT whenever they are written
+const DAY_IN_LEDGERS: u32 = 17_280;
+const BUMP_AMOUNT: u32 = 30 * DAY_IN_LEDGERS;
+const BUMP_THRESHOLD: u32 = BUMP_AMOUNT - DAY_IN_LEDGERS;
+
+// Upper bound on entries returned by the list views
+const MAX_PAGE_SIZE: u32 = 20;
+
 #[contract]
 pub struct Contract;
 
@@ -40,76 +75,443 @@ impl Contract {
     pub fn set_member(env: Env, address: Address, nickname: String) {
         address.require_auth();
         let member = Member { nickname, address: address.clone() };
-        env.storage().instance().set(&DataKey::Member(address), &member);
+        Self::save(&env, &DataKey::Member(address), &member);
     }
 
     pub fn get_member(env: Env, address: Address) -> Result<Member, Error> {
-        env.storage().instance().get(&DataKey::Member(address)).ok_or_else(|| Error::from_contract_error(1))
+        env.storage().persistent().get(&DataKey::Member(address)).ok_or_else(|| Error::from_contract_error(1))
     }
 
     // Group functions
-    pub fn set_group(env: Env, group_id: Symbol, owner: Address, members: Map<Address, i128>) {
-        let group = Group { group_id: group_id.clone(), owner, members };
-        env.storage().instance().set(&DataKey::Group(group_id.clone()), &group);
+    // Creates a group signed by its owner, every member starts at a zero
+    // balance and an existing group id is never replaced, including one
+    // still waiting in instance storage for `migrate`
+    pub fn set_group(env: Env, group_id: Symbol, owner: Address, members: Vec<Address>) -> Result<(), Error> {
+        owner.require_auth();
+        if env.storage().persistent().has(&DataKey::Group(group_id.clone()))
+            || env.storage().instance().has(&LegacyKey::Group(group_id.clone())) {
+            return Err(Error::from_contract_error(5));
+        }
 
-        // Update the list of all group IDs
-        let mut all_group_ids: Vec<Symbol> = env.storage().instance().get(&DataKey::AllGroupIds).unwrap_or_else(|| {
-            log!(&env, "Initializing new all_group_ids vector");
-            Vec::new(&env)
-        });
-        
-        if !all_group_ids.contains(&group_id) {
-            log!(&env, "Adding new group_id to all_group_ids: {:?}", group_id);
-            all_group_ids.push_back(group_id.clone());
-            env.storage().instance().set(&DataKey::AllGroupIds, &all_group_ids);
+        let mut balances = Map::new(&env);
+        balances.set(owner.clone(), 0);
+        for member in members.iter() {
+            balances.set(member, 0);
         }
+        let group = Group { group_id: group_id.clone(), owner, members: balances };
+        Self::save(&env, &DataKey::Group(group_id.clone()), &group);
 
-        log!(&env, "Current all_group_ids: {:?}", all_group_ids);
-    }
-    pub fn get_all_group_ids(env: Env) -> Vec<Symbol> {
-        let all_group_ids = env.storage().instance().get(&DataKey::AllGroupIds).unwrap_or_else(|| Vec::new(&env));
-        log!(&env, "Retrieved all_group_ids: {:?}", all_group_ids);
-        all_group_ids
+        // Update the list of all group IDs
+        log!(&env, "Adding new group_id to all group ids: {:?}", group_id);
+        Self::index_group(&env, &group_id);
+        Ok(())
     }
 
-    pub fn get_group(env: Env, group_id: Symbol) -> Result<Group, Error> {
-        env.storage().instance().get(&DataKey::Group(group_id)).ok_or_else(|| Error::from_contract_error(2))
+    // Group ids at positions [cursor, cursor + limit) in creation order
+    pub fn get_all_group_ids(env: Env, cursor: u32, limit: u32) -> Vec<Symbol> {
+        let count: u32 = env.storage().persistent().get(&DataKey::GroupCount).unwrap_or(0);
+        Self::page(&env, count, cursor, limit, DataKey::GroupIdAt)
     }
 
-    pub fn add_group_member(env: Env, group_id: Symbol, member_address: Address, balance: i128) -> Result<(), Error> {
-        let mut group: Group = env.storage().instance().get(&DataKey::Group(group_id.clone())).ok_or_else(|| Error::from_contract_error(2))?;
-        group.members.set(member_address, balance);
-        env.storage().instance().set(&DataKey::Group(group_id), &group);
-        Ok(())
+    pub fn get_group(env: Env, group_id: Symbol) -> Result<Group, Error> {
+        env.storage().persistent().get(&DataKey::Group(group_id)).ok_or_else(|| Error::from_contract_error(2))
     }
 
-    pub fn update_group_member_balance(env: Env, group_id: Symbol, member_address: Address, new_balance: i128) -> Result<(), Error> {
-        let mut group: Group = env.storage().instance().get(&DataKey::Group(group_id.clone())).ok_or_else(|| Error::from_contract_error(2))?;
-        group.members.set(member_address, new_balance);
-        env.storage().instance().set(&DataKey::Group(group_id), &group);
+    // Only the owner adds members, who join at a zero balance
+    pub fn add_group_member(env: Env, group_id: Symbol, member_address: Address) -> Result<(), Error> {
+        let mut group: Group = env.storage().persistent().get(&DataKey::Group(group_id.clone())).ok_or_else(|| Error::from_contract_error(2))?;
+        group.owner.require_auth();
+        if group.members.contains_key(member_address.clone()) {
+            return Err(Error::from_contract_error(5));
+        }
+        group.members.set(member_address, 0);
+        Self::save(&env, &DataKey::Group(group_id), &group);
         Ok(())
     }
 
     // Transaction functions
-    pub fn set_transaction(env: Env, user_id: Address, group_id: Symbol, amounts: Map<Address, i128>, proof: String) {
+    // Records an expense paid by `user_id`, where `amounts` holds each
+    // member's positive share. Balances only move through expenses: the
+    // payer is credited and each member debited once every other member
+    // in `amounts` has approved.
+    pub fn set_transaction(env: Env, user_id: Address, group_id: Symbol, amounts: Map<Address, i128>, proof: String) -> Result<u64, Error> {
+        user_id.require_auth();
+        let group: Group = env.storage().persistent().get(&DataKey::Group(group_id.clone())).ok_or_else(|| Error::from_contract_error(2))?;
+        if !group.members.contains_key(user_id.clone()) {
+            return Err(Error::from_contract_error(6));
+        }
+        for (member, amount) in amounts.iter() {
+            if !group.members.contains_key(member) {
+                return Err(Error::from_contract_error(6));
+            }
+            if amount <= 0 {
+                return Err(Error::from_contract_error(7));
+            }
+        }
+
         let transaction = Transaction {
-            user_id: user_id.clone(),
+            tx_id: 0,
+            user_id,
             group_id,
             amounts,
             proof,
             approvals: Vec::new(&env),
         };
-        env.storage().instance().set(&DataKey::Transaction(user_id), &transaction);
+        if Self::is_approved(&transaction) {
+            Self::apply(&env, &transaction);
+        }
+        Ok(Self::record_transaction(&env, transaction))
+    }
+
+    pub fn get_transaction(env: Env, tx_id: u64) -> Result<Transaction, Error> {
+        env.storage().persistent().get(&DataKey::Transaction(tx_id)).ok_or_else(|| Error::from_contract_error(3))
+    }
+
+    pub fn get_group_transactions(env: Env, group_id: Symbol, cursor: u32, limit: u32) -> Vec<u64> {
+        let count: u32 = env.storage().persistent().get(&DataKey::GroupTxCount(group_id.clone())).unwrap_or(0);
+        Self::page(&env, count, cursor, limit, |position| DataKey::GroupTxAt(group_id.clone(), position))
     }
 
-    pub fn get_transaction(env: Env, user_id: Address) -> Result<Transaction, Error> {
-        env.storage().instance().get(&DataKey::Transaction(user_id)).ok_or_else(|| Error::from_contract_error(3))
+    pub fn get_user_transactions(env: Env, user_id: Address, cursor: u32, limit: u32) -> Vec<u64> {
+        let count: u32 = env.storage().persistent().get(&DataKey::UserTxCount(user_id.clone())).unwrap_or(0);
+        Self::page(&env, count, cursor, limit, |position| DataKey::UserTxAt(user_id.clone(), position))
     }
 
-    pub fn add_approval(env: Env, user_id: Address, approver: Address) -> Result<(), Error> {
-        let mut transaction: Transaction = env.storage().instance().get(&DataKey::Transaction(user_id.clone())).ok_or_else(|| Error::from_contract_error(3))?;
+    // Approves one specific transaction, each approver counts once and must
+    // have a share in it
+    pub fn add_approval(env: Env, tx_id: u64, approver: Address) -> Result<(), Error> {
+        approver.require_auth();
+        let mut transaction: Transaction = env.storage().persistent().get(&DataKey::Transaction(tx_id)).ok_or_else(|| Error::from_contract_error(3))?;
+        if !transaction.amounts.contains_key(approver.clone()) {
+            return Err(Error::from_contract_error(8));
+        }
+        if transaction.approvals.contains(&approver) {
+            return Err(Error::from_contract_error(4));
+        }
+        let is_debtor = approver != transaction.user_id;
         transaction.approvals.push_back(approver);
-        env.storage().instance().set(&DataKey::Transaction(user_id), &transaction);
+        Self::save(&env, &DataKey::Transaction(tx_id), &transaction);
+
+        // Only the approval that completes the set applies the expense
+        if is_debtor && Self::is_approved(&transaction) {
+            Self::apply(&env, &transaction);
+        }
         Ok(())
     }
+
+    // Moves the instance entries of a contract deployed before the switch to
+    // persistent storage: every group listed in `AllGroupIds`, plus the
+    // members and the per-user transactions of the given addresses, which
+    // instance storage has no index of. Moved entries are removed from the
+    // instance, so running it again only picks up what is left. A group or
+    // member that already exists in persistent storage is not moved and
+    // stays in the instance, with its id left in `AllGroupIds`.
+    pub fn migrate(env: Env, members: Vec<Address>, users: Vec<Address>) {
+        let instance = env.storage().instance();
+
+        let group_ids: Vec<Symbol> = instance.get(&LegacyKey::AllGroupIds).unwrap_or_else(|| Vec::new(&env));
+        let mut conflicts = Vec::new(&env);
+        for group_id in group_ids.iter() {
+            if let Some(group) = instance.get::<LegacyKey, Group>(&LegacyKey::Group(group_id.clone())) {
+                if env.storage().persistent().has(&DataKey::Group(group_id.clone())) {
+                    log!(&env, "Group already exists, not migrated: {:?}", group_id);
+                    conflicts.push_back(group_id);
+                    continue;
+                }
+                Self::save(&env, &DataKey::Group(group_id.clone()), &group);
+                Self::index_group(&env, &group_id);
+                instance.remove(&LegacyKey::Group(group_id));
+            }
+        }
+        if conflicts.is_empty() {
+            instance.remove(&LegacyKey::AllGroupIds);
+        } else {
+            instance.set(&LegacyKey::AllGroupIds, &conflicts);
+        }
+
+        for address in members.iter() {
+            if let Some(member) = instance.get::<LegacyKey, Member>(&LegacyKey::Member(address.clone())) {
+                if env.storage().persistent().has(&DataKey::Member(address.clone())) {
+                    log!(&env, "Member already exists, not migrated: {:?}", address);
+                    continue;
+                }
+                Self::save(&env, &DataKey::Member(address.clone()), &member);
+                instance.remove(&LegacyKey::Member(address));
+            }
+        }
+
+        for user_id in users.iter() {
+            if let Some(legacy) = instance.get::<LegacyKey, LegacyTransaction>(&LegacyKey::Transaction(user_id.clone())) {
+                let transaction = Transaction {
+                    tx_id: 0,
+                    user_id: legacy.user_id,
+                    group_id: legacy.group_id,
+                    amounts: legacy.amounts,
+                    proof: legacy.proof,
+                    approvals: legacy.approvals,
+                };
+                Self::record_transaction(&env, transaction);
+                instance.remove(&LegacyKey::Transaction(user_id));
+            }
+        }
+    }
+
+    // Assigns the next id to `transaction`, stores it and appends it to the
+    // group and user lists
+    fn record_transaction(env: &Env, mut transaction: Transaction) -> u64 {
+        let tx_id = env.storage().persistent().get::<DataKey, u64>(&DataKey::LastTransactionId).unwrap_or(0) + 1;
+        Self::save(env, &DataKey::LastTransactionId, &tx_id);
+        transaction.tx_id = tx_id;
+        Self::save(env, &DataKey::Transaction(tx_id), &transaction);
+
+        let group_count: u32 = env.storage().persistent().get(&DataKey::GroupTxCount(transaction.group_id.clone())).unwrap_or(0);
+        Self::save(env, &DataKey::GroupTxAt(transaction.group_id.clone(), group_count), &tx_id);
+        Self::save(env, &DataKey::GroupTxCount(transaction.group_id.clone()), &(group_count + 1));
+
+        let user_count: u32 = env.storage().persistent().get(&DataKey::UserTxCount(transaction.user_id.clone())).unwrap_or(0);
+        Self::save(env, &DataKey::UserTxAt(transaction.user_id.clone(), user_count), &tx_id);
+        Self::save(env, &DataKey::UserTxCount(transaction.user_id), &(user_count + 1));
+
+        tx_id
+    }
+
+    fn is_approved(transaction: &Transaction) -> bool {
+        transaction.amounts.keys().iter()
+            .all(|member| member == transaction.user_id || transaction.approvals.contains(&member))
+    }
+
+    // Credits the payer with the total and debits every member its share
+    fn apply(env: &Env, transaction: &Transaction) {
+        let key = DataKey::Group(transaction.group_id.clone());
+        let Some(mut group) = env.storage().persistent().get::<DataKey, Group>(&key) else {
+            return;
+        };
+        let mut total: i128 = 0;
+        for (member, amount) in transaction.amounts.iter() {
+            total += amount;
+            let balance = group.members.get(member.clone()).unwrap_or(0);
+            group.members.set(member, balance - amount);
+        }
+        let balance = group.members.get(transaction.user_id.clone()).unwrap_or(0);
+        group.members.set(transaction.user_id.clone(), balance + total);
+        Self::save(env, &key, &group);
+    }
+
+    // Reads positions [cursor, cursor + limit) of an index holding `count`
+    // entries, at most MAX_PAGE_SIZE of them
+    fn page<V: IntoVal<Env, Val> + TryFromVal<Env, Val>>(env: &Env, count: u32, cursor: u32, limit: u32, key_at: impl Fn(u32) -> DataKey) -> Vec<V> {
+        let end = cursor.saturating_add(limit.min(MAX_PAGE_SIZE)).min(count);
+        let mut entries = Vec::new(env);
+        for position in cursor..end {
+            if let Some(entry) = env.storage().persistent().get::<DataKey, V>(&key_at(position)) {
+                entries.push_back(entry);
+            }
+        }
+        entries
+    }
+
+    fn index_group(env: &Env, group_id: &Symbol) {
+        let count: u32 = env.storage().persistent().get(&DataKey::GroupCount).unwrap_or(0);
+        Self::save(env, &DataKey::GroupIdAt(count), group_id);
+        Self::save(env, &DataKey::GroupCount, &(count + 1));
+    }
+
+    fn save<V: IntoVal<Env, Val>>(env: &Env, key: &DataKey, value: &V) {
+        env.storage().persistent().set(key, value);
+        env.storage().persistent().extend_ttl(key, BUMP_THRESHOLD, BUMP_AMOUNT);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use soroban_sdk::{symbol_short, testutils::{Address as _, MockAuth, MockAuthInvoke}};
+
+    // Group "trip" owned by the first of two members
+    fn setup(env: &Env) -> (ContractClient<'_>, Symbol, Address, Address) {
+        env.mock_all_auths();
+        let client = ContractClient::new(env, &env.register_contract(None, Contract));
+        let alice = Address::generate(env);
+        let bob = Address::generate(env);
+        let group_id = symbol_short!("trip");
+        client.set_group(&group_id, &alice, &Vec::from_array(env, [bob.clone()]));
+        (client, group_id, alice, bob)
+    }
+
+    fn split(env: &Env, shares: &[(&Address, i128)]) -> Map<Address, i128> {
+        let mut amounts = Map::new(env);
+        for (member, amount) in shares {
+            amounts.set((*member).clone(), *amount);
+        }
+        amounts
+    }
+
+    #[test]
+    fn test_transactions_get_sequential_ids_and_lists() {
+        let env = Env::default();
+        let (client, group_id, alice, bob) = setup(&env);
+        let proof = String::from_str(&env, "receipt");
+        let other = symbol_short!("flat");
+        client.set_group(&other, &bob, &Vec::new(&env));
+
+        assert_eq!(client.set_transaction(&alice, &group_id, &split(&env, &[(&bob, 10)]), &proof), 1);
+        assert_eq!(client.set_transaction(&bob, &other, &split(&env, &[(&bob, 20)]), &proof), 2);
+        assert_eq!(client.set_transaction(&bob, &group_id, &split(&env, &[(&alice, 30)]), &proof), 3);
+
+        assert_eq!(client.get_transaction(&2).group_id, other);
+        assert_eq!(client.get_group_transactions(&group_id, &0, &10), Vec::from_array(&env, [1, 3]));
+        assert_eq!(client.get_user_transactions(&bob, &0, &10), Vec::from_array(&env, [2, 3]));
+        assert_eq!(client.get_user_transactions(&bob, &1, &10), Vec::from_array(&env, [3]));
+        assert_eq!(client.try_get_transaction(&4).err(), Some(Ok(Error::from_contract_error(3))));
+    }
+
+    #[test]
+    fn test_approvals_belong_to_one_transaction() {
+        let env = Env::default();
+        let (client, group_id, alice, bob) = setup(&env);
+        let proof = String::from_str(&env, "receipt");
+        let first = client.set_transaction(&alice, &group_id, &split(&env, &[(&bob, 10)]), &proof);
+        let second = client.set_transaction(&alice, &group_id, &split(&env, &[(&bob, 10)]), &proof);
+
+        client.add_approval(&first, &bob);
+        assert_eq!(client.get_transaction(&first).approvals, Vec::from_array(&env, [bob.clone()]));
+        assert!(client.get_transaction(&second).approvals.is_empty());
+        assert_eq!(client.try_add_approval(&first, &bob), Err(Ok(Error::from_contract_error(4))));
+        assert_eq!(client.try_add_approval(&7, &bob), Err(Ok(Error::from_contract_error(3))));
+    }
+
+    #[test]
+    fn test_list_views_are_paged() {
+        let env = Env::default();
+        let (client, group_id, alice, bob) = setup(&env);
+        for _ in 0..25 {
+            client.set_transaction(&alice, &group_id, &split(&env, &[(&bob, 1)]), &String::from_str(&env, "receipt"));
+        }
+
+        assert_eq!(client.get_group_transactions(&group_id, &0, &100).len(), MAX_PAGE_SIZE);
+        let last = client.get_group_transactions(&group_id, &20, &10);
+        assert_eq!(last, Vec::from_array(&env, [21, 22, 23, 24, 25]));
+        assert_eq!(client.get_user_transactions(&alice, &24, &10), Vec::from_array(&env, [25]));
+        assert_eq!(client.get_all_group_ids(&0, &10), Vec::from_array(&env, [group_id]));
+    }
+
+    #[test]
+    fn test_migrate_moves_legacy_instance_entries() {
+        let env = Env::default();
+        env.mock_all_auths();
+        let client = ContractClient::new(&env, &env.register_contract(None, Contract));
+        let alice = Address::generate(&env);
+        let bob = Address::generate(&env);
+        let group_id = symbol_short!("trip");
+        let amounts = split(&env, &[(&bob, 10)]);
+        env.as_contract(&client.address, || {
+            let instance = env.storage().instance();
+            instance.set(&LegacyKey::AllGroupIds, &Vec::from_array(&env, [group_id.clone()]));
+            instance.set(&LegacyKey::Group(group_id.clone()), &Group { group_id: group_id.clone(), owner: alice.clone(), members: split(&env, &[(&alice, 10), (&bob, -10)]) });
+            for (address, nickname) in [(&alice, "alice"), (&bob, "bob")] {
+                instance.set(&LegacyKey::Member(address.clone()), &Member { nickname: String::from_str(&env, nickname), address: address.clone() });
+            }
+            let legacy = LegacyTransaction { user_id: alice.clone(), group_id: group_id.clone(), amounts: amounts.clone(), proof: String::from_str(&env, "receipt"), approvals: Vec::new(&env) };
+            instance.set(&LegacyKey::Transaction(alice.clone()), &legacy);
+        });
+
+        // bob set his nickname after the upgrade, the legacy one must not replace it
+        client.set_member(&bob, &String::from_str(&env, "bobby"));
+        let everyone = Vec::from_array(&env, [alice.clone(), bob.clone()]);
+        client.migrate(&everyone, &everyone);
+
+        assert_eq!(client.get_all_group_ids(&0, &10), Vec::from_array(&env, [group_id.clone()]));
+        assert_eq!(client.get_group(&group_id).members.get(bob.clone()), Some(-10));
+        assert_eq!(client.get_member(&alice).nickname, String::from_str(&env, "alice"));
+        assert_eq!(client.get_member(&bob).nickname, String::from_str(&env, "bobby"));
+        assert_eq!(client.get_transaction(&1).amounts, amounts);
+        assert_eq!(client.get_group_transactions(&group_id, &0, &10), Vec::from_array(&env, [1]));
+        assert_eq!(client.get_user_transactions(&alice, &0, &10), Vec::from_array(&env, [1]));
+        env.as_contract(&client.address, || {
+            let instance = env.storage().instance();
+            assert!(!instance.has(&LegacyKey::AllGroupIds));
+            assert!(!instance.has(&LegacyKey::Transaction(alice.clone())));
+            assert!(instance.has(&LegacyKey::Member(bob.clone())));
+        });
+
+        // A second run finds nothing left to move
+        client.migrate(&everyone, &everyone);
+        assert_eq!(client.get_all_group_ids(&0, &10).len(), 1);
+        assert_eq!(client.get_user_transactions(&alice, &0, &10).len(), 1);
+        assert_eq!(client.get_member(&bob).nickname, String::from_str(&env, "bobby"));
+    }
+
+    #[test]
+    fn test_only_the_owner_manages_a_group() {
+        let env = Env::default();
+        let (client, group_id, alice, bob) = setup(&env);
+        let mallory = Address::generate(&env);
+
+        assert_eq!(client.try_set_group(&group_id, &mallory, &Vec::new(&env)).err(), Some(Ok(Error::from_contract_error(5))));
+        env.as_contract(&client.address, || {
+            let legacy = Group { group_id: symbol_short!("old"), owner: bob.clone(), members: Map::new(&env) };
+            env.storage().instance().set(&LegacyKey::Group(symbol_short!("old")), &legacy);
+        });
+        assert_eq!(client.try_set_group(&symbol_short!("old"), &mallory, &Vec::new(&env)).err(), Some(Ok(Error::from_contract_error(5))));
+
+        // Signed by mallory alone, naming alice as the owner does not help
+        let members: Vec<Address> = Vec::new(&env);
+        env.mock_auths(&[MockAuth {
+            address: &mallory,
+            invoke: &MockAuthInvoke {
+                contract: &client.address,
+                fn_name: "set_group",
+                args: (symbol_short!("new"), alice.clone(), members.clone()).into_val(&env),
+                sub_invokes: &[],
+            },
+        }]);
+        assert!(client.try_set_group(&symbol_short!("new"), &alice, &members).is_err());
+
+        env.mock_auths(&[MockAuth {
+            address: &mallory,
+            invoke: &MockAuthInvoke {
+                contract: &client.address,
+                fn_name: "add_group_member",
+                args: (group_id.clone(), mallory.clone()).into_val(&env),
+                sub_invokes: &[],
+            },
+        }]);
+        assert!(client.try_add_group_member(&group_id, &mallory).is_err());
+        assert!(!client.get_group(&group_id).members.contains_key(mallory));
+    }
+
+    #[test]
+    fn test_balances_move_once_every_debtor_approves() {
+        let env = Env::default();
+        let (client, group_id, alice, bob) = setup(&env);
+        let carol = Address::generate(&env);
+        client.add_group_member(&group_id, &carol);
+        assert_eq!(client.try_add_group_member(&group_id, &carol).err(), Some(Ok(Error::from_contract_error(5))));
+
+        let amounts = split(&env, &[(&alice, 10), (&bob, 10), (&carol, 10)]);
+        let tx_id = client.set_transaction(&alice, &group_id, &amounts, &String::from_str(&env, "receipt"));
+        let balance = |member: &Address| client.get_group(&group_id).members.get(member.clone());
+
+        client.add_approval(&tx_id, &bob);
+        assert_eq!(balance(&alice), Some(0));
+        client.add_approval(&tx_id, &carol);
+        assert_eq!((balance(&alice), balance(&bob), balance(&carol)), (Some(20), Some(-10), Some(-10)));
+
+        // Later approvals do not apply it again
+        client.add_approval(&tx_id, &alice);
+        assert_eq!(balance(&alice), Some(20));
+
+        // Only addresses with a share can approve
+        let dave = Address::generate(&env);
+        client.add_group_member(&group_id, &dave);
+        let lunch = client.set_transaction(&alice, &group_id, &split(&env, &[(&bob, 10)]), &String::from_str(&env, "receipt"));
+        assert_eq!(client.try_add_approval(&lunch, &dave).err(), Some(Ok(Error::from_contract_error(8))));
+        assert_eq!(client.try_add_approval(&lunch, &Address::generate(&env)).err(), Some(Ok(Error::from_contract_error(8))));
+        assert!(client.get_transaction(&lunch).approvals.is_empty());
+        let negative = split(&env, &[(&bob, -5)]);
+        assert_eq!(
+            client.try_set_transaction(&alice, &group_id, &negative, &String::from_str(&env, "receipt")).err(),
+            Some(Ok(Error::from_contract_error(7))),
+        );
+    }
 }
@@ -0,0 +1,172 @@
+#![no_std]
+use soroban_sdk::{contract, contracterror, contractimpl, contracttype, panic_with_error, Address, Env, String, Symbol};
+
+#[derive(Clone, PartialEq, Eq)]
+#[contracttype]
+pub enum DataKey {
+    Handle(Symbol),   // normalized handle -> owner
+    Owner(Address),   // owner -> normalized handle, one handle per address
+}
+
+#[contracterror]
+#[derive(Copy, Clone, Debug, Eq, PartialEq, PartialOrd, Ord)]
+#[repr(u32)]
+pub enum Error {
+    InvalidHandle = 1,
+    HandleTaken = 2,
+    HandleNotFound = 3,
+    AlreadyRegistered = 4,
+}
+
+// Handles are 3 to 32 characters out of [a-z0-9_] once lowercased, so they
+// also work as user ids in the splitter
+const MIN_HANDLE_LEN: usize = 3;
+const MAX_HANDLE_LEN: usize = 32;
+
+const DAY_IN_LEDGERS: u32 = 17_280;
+const BUMP_AMOUNT: u32 = 30 * DAY_IN_LEDGERS;
+const BUMP_THRESHOLD: u32 = BUMP_AMOUNT - DAY_IN_LEDGERS;
+
+#[contract]
+pub struct UsernameRegistry;
+
+#[contractimpl]
+impl UsernameRegistry {
+    // Claims `handle` for `owner` and returns it normalized
+    pub fn register(env: &Env, handle: String, owner: Address) -> Symbol {
+        owner.require_auth();
+        let handle = Self::normalize(env, &handle);
+
+        if env.storage().persistent().has(&DataKey::Handle(handle.clone())) {
+            panic_with_error!(env, Error::HandleTaken);
+        }
+        if env.storage().persistent().has(&DataKey::Owner(owner.clone())) {
+            panic_with_error!(env, Error::AlreadyRegistered);
+        }
+
+        Self::assign(env, &handle, &owner);
+        env.events().publish((Symbol::new(env, "registered"), owner), handle.clone());
+        handle
+    }
+
+    pub fn resolve(env: &Env, handle: String) -> Address {
+        Self::owner_of(env, Self::normalize(env, &handle))
+            .unwrap_or_else(|| panic_with_error!(env, Error::HandleNotFound))
+    }
+
+    // Exact lookup of an already normalized handle, for other contracts
+    pub fn owner_of(env: &Env, handle: Symbol) -> Option<Address> {
+        let key = DataKey::Handle(handle);
+        let owner = env.storage().persistent().get::<DataKey, Address>(&key)?;
+        env.storage().persistent().extend_ttl(&key, BUMP_THRESHOLD, BUMP_AMOUNT);
+        Some(owner)
+    }
+
+    pub fn handle_of(env: &Env, owner: Address) -> Option<Symbol> {
+        let key = DataKey::Owner(owner);
+        let handle = env.storage().persistent().get::<DataKey, Symbol>(&key)?;
+        env.storage().persistent().extend_ttl(&key, BUMP_THRESHOLD, BUMP_AMOUNT);
+        Some(handle)
+    }
+
+    // Both sides sign: the handle becomes the reverse lookup of `to`
+    pub fn transfer(env: &Env, handle: String, to: Address) {
+        let handle = Self::normalize(env, &handle);
+        let from = Self::owner_of(env, handle.clone())
+            .unwrap_or_else(|| panic_with_error!(env, Error::HandleNotFound));
+        from.require_auth();
+        to.require_auth();
+
+        if env.storage().persistent().has(&DataKey::Owner(to.clone())) {
+            panic_with_error!(env, Error::AlreadyRegistered);
+        }
+
+        env.storage().persistent().remove(&DataKey::Owner(from.clone()));
+        Self::assign(env, &handle, &to);
+        env.events().publish((Symbol::new(env, "transferred"), from, to), handle);
+    }
+
+    pub fn release(env: &Env, handle: String) {
+        let handle = Self::normalize(env, &handle);
+        let owner = Self::owner_of(env, handle.clone())
+            .unwrap_or_else(|| panic_with_error!(env, Error::HandleNotFound));
+        owner.require_auth();
+
+        env.storage().persistent().remove(&DataKey::Handle(handle.clone()));
+        env.storage().persistent().remove(&DataKey::Owner(owner.clone()));
+        env.events().publish((Symbol::new(env, "released"), owner), handle);
+    }
+
+    fn assign(env: &Env, handle: &Symbol, owner: &Address) {
+        let handle_key = DataKey::Handle(handle.clone());
+        let owner_key = DataKey::Owner(owner.clone());
+        env.storage().persistent().set(&handle_key, owner);
+        env.storage().persistent().set(&owner_key, handle);
+        env.storage().persistent().extend_ttl(&handle_key, BUMP_THRESHOLD, BUMP_AMOUNT);
+        env.storage().persistent().extend_ttl(&owner_key, BUMP_THRESHOLD, BUMP_AMOUNT);
+    }
+
+    // Lowercases ASCII letters and rejects anything outside [a-z0-9_]
+    fn normalize(env: &Env, handle: &String) -> Symbol {
+        let len = handle.len() as usize;
+        if !(MIN_HANDLE_LEN..=MAX_HANDLE_LEN).contains(&len) {
+            panic_with_error!(env, Error::InvalidHandle);
+        }
+
+        let mut buf = [0u8; MAX_HANDLE_LEN];
+        handle.copy_into_slice(&mut buf[..len]);
+        for byte in buf[..len].iter_mut() {
+            byte.make_ascii_lowercase();
+            if !(byte.is_ascii_lowercase() || byte.is_ascii_digit() || *byte == b'_') {
+                panic_with_error!(env, Error::InvalidHandle);
+            }
+        }
+        let text = core::str::from_utf8(&buf[..len]).unwrap_or_else(|_| panic_with_error!(env, Error::InvalidHandle));
+        Symbol::new(env, text)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use soroban_sdk::testutils::Address as _;
+
+    #[test]
+    fn test_handles_are_unique_regardless_of_case() {
+        let env = Env::default();
+        env.mock_all_auths();
+        let client = UsernameRegistryClient::new(&env, &env.register_contract(None, UsernameRegistry));
+        let alice = Address::generate(&env);
+        let bob = Address::generate(&env);
+
+        let handle = client.register(&String::from_str(&env, "Alice_01"), &alice);
+        assert_eq!(handle, Symbol::new(&env, "alice_01"));
+        assert_eq!(client.resolve(&String::from_str(&env, "ALICE_01")), alice);
+        assert_eq!(client.handle_of(&alice), Some(handle.clone()));
+
+        assert_eq!(client.try_register(&String::from_str(&env, "alice_01"), &bob), Err(Ok(Error::HandleTaken.into())));
+        assert_eq!(client.try_register(&String::from_str(&env, "al ice"), &bob), Err(Ok(Error::InvalidHandle.into())));
+        assert_eq!(client.try_register(&String::from_str(&env, "al"), &bob), Err(Ok(Error::InvalidHandle.into())));
+    }
+
+    #[test]
+    fn test_transfer_and_release() {
+        let env = Env::default();
+        env.mock_all_auths();
+        let client = UsernameRegistryClient::new(&env, &env.register_contract(None, UsernameRegistry));
+        let alice = Address::generate(&env);
+        let bob = Address::generate(&env);
+        let handle = String::from_str(&env, "alice");
+        client.register(&handle, &alice);
+
+        client.transfer(&handle, &bob);
+        assert_eq!(client.resolve(&handle), bob);
+        assert_eq!(client.handle_of(&alice), None);
+        assert_eq!(client.handle_of(&bob), Some(Symbol::new(&env, "alice")));
+
+        client.release(&handle);
+        assert_eq!(client.owner_of(&Symbol::new(&env, "alice")), None);
+        assert_eq!(client.try_resolve(&handle), Err(Ok(Error::HandleNotFound.into())));
+        client.register(&handle, &alice);
+    }
+}